@@ -2,6 +2,9 @@ pub mod ata_utils;
 pub mod market_event;
 pub mod orderbook;
 pub mod packet_decoder;
+pub mod quantities_ext;
 pub mod sdk_client_core;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_support;
 #[cfg(test)]
 pub mod test_unit_conversion;
@@ -1,18 +1,788 @@
 #[allow(unused_imports)]
 use borsh::{BorshDeserialize, BorshSerialize};
-use phoenix::state::OrderPacket;
+use phoenix::quantities::{Ticks, WrapperU64};
+use phoenix::state::markets::FIFOOrderId;
+use phoenix::state::{OrderPacket, OrderPacketMetadata, SelfTradeBehavior, Side};
+use solana_sdk::hash::hash;
+use solana_sdk::pubkey::Pubkey;
 
+use crate::sdk_client_core::{MarketMetadata, PhoenixOrder};
+
+/// Borsh tag (first byte) of a serialized `OrderPacket::PostOnly`.
+pub const POST_ONLY_TAG: u8 = 0;
+/// Borsh tag (first byte) of a serialized `OrderPacket::Limit`.
+pub const LIMIT_TAG: u8 = 1;
+/// Borsh tag (first byte) of a serialized `OrderPacket::ImmediateOrCancel`.
+pub const IMMEDIATE_OR_CANCEL_TAG: u8 = 2;
+
+/// Returns the Borsh tag `packet` is serialized with. This is a free function rather than a
+/// method on `OrderPacketExt` because trait methods cannot be `const`. The rest of the encoding
+/// goes through Borsh, which is not usable in a `const` context.
+pub const fn order_packet_tag(packet: &OrderPacket) -> u8 {
+    match packet {
+        OrderPacket::PostOnly { .. } => POST_ONLY_TAG,
+        OrderPacket::Limit { .. } => LIMIT_TAG,
+        OrderPacket::ImmediateOrCancel { .. } => IMMEDIATE_OR_CANCEL_TAG,
+    }
+}
+
+/// Serialized length of an `OrderPacket::PostOnly` with every optional field set to `None`.
+pub const POST_ONLY_CURRENT_LEN: usize = 39;
+/// Serialized length of a legacy `OrderPacket::PostOnly` that omits the two trailing bytes.
+pub const POST_ONLY_DEPRECATED_LEN: usize = POST_ONLY_CURRENT_LEN - 2;
+
+/// Serialized length of an `OrderPacket::Limit` with every optional field set to `None`.
+pub const LIMIT_CURRENT_LEN: usize = 40;
+/// Serialized length of a legacy `OrderPacket::Limit` that omits the two trailing bytes.
+pub const LIMIT_DEPRECATED_LEN: usize = LIMIT_CURRENT_LEN - 2;
+
+/// Serialized length of an `OrderPacket::ImmediateOrCancel` with every optional field set to
+/// `None`.
+pub const IMMEDIATE_OR_CANCEL_CURRENT_LEN: usize = 56;
+/// Serialized length of a legacy `OrderPacket::ImmediateOrCancel` that omits the two trailing
+/// bytes.
+pub const IMMEDIATE_OR_CANCEL_DEPRECATED_LEN: usize = IMMEDIATE_OR_CANCEL_CURRENT_LEN - 2;
+
+/// Each `Some` optional field adds its 8 byte payload on top of the 1 byte `Option` tag.
+const OPTION_PAYLOAD_LEN: usize = 8;
+
+/// Longest serialized `OrderPacket`: an `ImmediateOrCancel` with all four optional fields set.
+const MAX_ORDER_PACKET_LEN: usize = IMMEDIATE_OR_CANCEL_CURRENT_LEN + 4 * OPTION_PAYLOAD_LEN;
+
+/// A flat record of an order packet for audit logs, with the differences between packet types
+/// flattened into optional fields and every price and size given in both lots and UI units.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct OrderAuditRecord {
+    /// `PostOnly`, `Limit`, or `ImmediateOrCancel`.
+    pub order_type: String,
+    pub side: Side,
+    /// None for IOC orders without a limit price.
+    pub price_in_ticks: Option<u64>,
+    pub price_ui: Option<f64>,
+    pub num_base_lots: u64,
+    pub base_ui: f64,
+    /// Only IOC orders can be sized in quote lots, so this is 0 for other packet types.
+    pub num_quote_lots: u64,
+    pub quote_ui: f64,
+    pub client_order_id: u128,
+    /// None for post-only orders, which never match.
+    pub self_trade_behavior: Option<SelfTradeBehavior>,
+    pub last_valid_slot: Option<u64>,
+    pub last_valid_unix_timestamp_in_seconds: Option<u64>,
+    /// Whether the packet was decoded from the deprecated layout.
+    pub was_deprecated: bool,
+}
+
+pub trait OrderPacketExt {
+    /// Returns the number of bytes this packet occupies when serialized in the current layout.
+    fn serialized_len(&self) -> usize;
+
+    /// Returns a human readable summary of the packet with the price and size converted to UI
+    /// amounts using the market's metadata, e.g. `Limit Bid 0.01 @ 22.719`.
+    /// IOC orders without a limit price are shown at `market`, and IOC orders sized in quote lots
+    /// show the quote amount instead of a base amount.
+    fn describe(&self, metadata: &MarketMetadata) -> String;
+
+    /// Returns the number of accounts the new order instruction for this packet references,
+    /// including the Phoenix program itself. Packets with `use_only_deposited_funds` set trade
+    /// against the seat's free funds and skip the token accounts, vaults, and token program.
+    fn required_account_count(&self) -> usize;
+
+    /// Returns the packet with representations that the matching engine treats identically
+    /// rewritten to a single form, so `PartialEq` compares what the order does rather than how it
+    /// was encoded:
+    /// - An IOC limit price at the extreme of its side (`Ticks::MAX` for a bid, `Ticks::MIN` for
+    ///   an ask) is the same as no limit, and becomes `None`.
+    /// - A `match_limit` of `Some(u64::MAX)` is the same as no match limit, and becomes `None`.
+    ///
+    /// All other fields, including expiry and client order id, are left as they are.
+    fn canonicalize(&self) -> Self;
+
+    /// Flattens the packet into an `OrderAuditRecord`, converting prices and sizes to UI amounts
+    /// with the market's metadata. `was_deprecated` records whether the packet was decoded from
+    /// the deprecated layout.
+    ///
+    /// Returns an error if any UI amount is not finite, e.g. because `metadata` is zeroed, since
+    /// Borsh refuses to serialize a NaN.
+    fn to_audit_record(
+        &self,
+        metadata: &MarketMetadata,
+        was_deprecated: bool,
+    ) -> anyhow::Result<OrderAuditRecord>;
+
+    /// Returns true if the packets are identical apart from their `client_order_id`, i.e. they
+    /// describe the same order even if the client order id was regenerated on resubmission.
+    fn eq_ignoring_client_id(&self, other: &Self) -> bool;
+
+    /// Returns the packet with its `client_order_id` set to 0 and every other field unchanged,
+    /// for logging to places where the client order id should not be exposed.
+    fn redacted(&self) -> Self;
+
+    /// Returns a `Display` wrapper that prints the packet's fields without its `client_order_id`.
+    fn redacted_display(&self) -> RedactedOrderPacket<'_>;
+
+    /// Returns the order this packet would rest as if it posted in full, keyed the way an
+    /// `Orderbook<FIFOOrderId, PhoenixOrder>` stores it, so a synthetic copy of the order can be
+    /// added to a book for simulation. `order_sequence_number` is the market's sequence number;
+    /// for bids it is inverted, as the program does, so the id sorts in matching priority.
+    /// Returns None for IOC packets, which never rest.
+    fn as_resting_order(
+        &self,
+        trader: Pubkey,
+        order_sequence_number: u64,
+    ) -> Option<(FIFOOrderId, PhoenixOrder)>;
+
+    /// Returns a SHA-256 hash of the packet that ignores its `client_order_id`, for joining
+    /// submitted packets with on-chain activity when client order ids were remapped. The hash
+    /// covers the current layout serialization of `redacted()`: the variant tag and every field
+    /// other than `client_order_id`, i.e. side, price, sizes, self trade behavior, match limit,
+    /// expiry, and the deposit and slide flags. Packets that differ only in how they encode the
+    /// same order (see `canonicalize`) hash differently.
+    fn content_hash(&self) -> [u8; 32];
+}
+
+impl OrderPacketExt for OrderPacket {
+    fn serialized_len(&self) -> usize {
+        let (base_len, optional_fields) = match self {
+            OrderPacket::PostOnly {
+                last_valid_slot,
+                last_valid_unix_timestamp_in_seconds,
+                ..
+            } => (
+                POST_ONLY_CURRENT_LEN,
+                [
+                    None,
+                    *last_valid_slot,
+                    *last_valid_unix_timestamp_in_seconds,
+                ],
+            ),
+            OrderPacket::Limit {
+                match_limit,
+                last_valid_slot,
+                last_valid_unix_timestamp_in_seconds,
+                ..
+            } => (
+                LIMIT_CURRENT_LEN,
+                [
+                    *match_limit,
+                    *last_valid_slot,
+                    *last_valid_unix_timestamp_in_seconds,
+                ],
+            ),
+            OrderPacket::ImmediateOrCancel {
+                price_in_ticks,
+                match_limit,
+                last_valid_slot,
+                last_valid_unix_timestamp_in_seconds,
+                ..
+            } => (
+                IMMEDIATE_OR_CANCEL_CURRENT_LEN + price_in_ticks.map_or(0, |_| OPTION_PAYLOAD_LEN),
+                [
+                    *match_limit,
+                    *last_valid_slot,
+                    *last_valid_unix_timestamp_in_seconds,
+                ],
+            ),
+        };
+        base_len
+            + optional_fields
+                .iter()
+                .filter(|field| field.is_some())
+                .count()
+                * OPTION_PAYLOAD_LEN
+    }
+
+    fn describe(&self, metadata: &MarketMetadata) -> String {
+        let base_ui = |base_lots: u64| {
+            metadata
+                .base_atoms_to_raw_base_units_as_float(metadata.base_lots_to_base_atoms(base_lots))
+        };
+        let size = match self {
+            OrderPacket::ImmediateOrCancel {
+                num_base_lots,
+                num_quote_lots,
+                ..
+            } if num_base_lots.as_u64() == 0 && num_quote_lots.as_u64() > 0 => format!(
+                "{} (quote)",
+                metadata.quote_atoms_to_quote_units_as_float(
+                    metadata.quote_lots_to_quote_atoms(num_quote_lots.as_u64())
+                )
+            ),
+            _ => base_ui(self.num_base_lots().as_u64()).to_string(),
+        };
+        let price = match self {
+            OrderPacket::ImmediateOrCancel {
+                price_in_ticks: None,
+                ..
+            } => "market".to_string(),
+            _ => metadata
+                .ticks_to_float_price(self.get_price_in_ticks().as_u64())
+                .to_string(),
+        };
+        format!(
+            "{} {:?} {} @ {}",
+            order_type_name(self),
+            self.side(),
+            size,
+            price
+        )
+    }
+
+    fn required_account_count(&self) -> usize {
+        if self.no_deposit_or_withdrawal() {
+            // Program, log authority, market, trader, seat
+            5
+        } else if self.is_take_only() {
+            // Program, log authority, market, trader, base and quote token accounts,
+            // base and quote vaults, token program
+            9
+        } else {
+            // Swap accounts plus the seat
+            10
+        }
+    }
+
+    fn canonicalize(&self) -> Self {
+        let mut packet = *self;
+        let side = packet.side();
+        match &mut packet {
+            OrderPacket::PostOnly { .. } => {}
+            OrderPacket::Limit { match_limit, .. } => {
+                if *match_limit == Some(u64::MAX) {
+                    *match_limit = None;
+                }
+            }
+            OrderPacket::ImmediateOrCancel {
+                price_in_ticks,
+                match_limit,
+                ..
+            } => {
+                let market_price_in_ticks = match side {
+                    Side::Bid => Ticks::MAX,
+                    Side::Ask => Ticks::MIN,
+                };
+                if *price_in_ticks == Some(market_price_in_ticks) {
+                    *price_in_ticks = None;
+                }
+                if *match_limit == Some(u64::MAX) {
+                    *match_limit = None;
+                }
+            }
+        }
+        packet
+    }
+
+    fn to_audit_record(
+        &self,
+        metadata: &MarketMetadata,
+        was_deprecated: bool,
+    ) -> anyhow::Result<OrderAuditRecord> {
+        let price_in_ticks = match self {
+            OrderPacket::ImmediateOrCancel { price_in_ticks, .. } => {
+                price_in_ticks.map(|price_in_ticks| price_in_ticks.as_u64())
+            }
+            _ => Some(self.get_price_in_ticks().as_u64()),
+        };
+        let num_base_lots = self.num_base_lots().as_u64();
+        let num_quote_lots = self.num_quote_lots().as_u64();
+        let price_ui =
+            price_in_ticks.map(|price_in_ticks| metadata.ticks_to_float_price(price_in_ticks));
+        let base_ui = metadata
+            .base_atoms_to_raw_base_units_as_float(metadata.base_lots_to_base_atoms(num_base_lots));
+        let quote_ui = metadata.quote_atoms_to_quote_units_as_float(
+            metadata.quote_lots_to_quote_atoms(num_quote_lots),
+        );
+        if !price_ui
+            .into_iter()
+            .chain([base_ui, quote_ui])
+            .all(f64::is_finite)
+        {
+            return Err(anyhow::anyhow!(
+                "Cannot convert the packet to UI amounts with the given market metadata"
+            ));
+        }
+        Ok(OrderAuditRecord {
+            order_type: order_type_name(self).to_string(),
+            side: self.side(),
+            price_in_ticks,
+            price_ui,
+            num_base_lots,
+            base_ui,
+            num_quote_lots,
+            quote_ui,
+            client_order_id: self.client_order_id(),
+            self_trade_behavior: match self {
+                OrderPacket::PostOnly { .. } => None,
+                _ => Some(self.self_trade_behavior()),
+            },
+            last_valid_slot: self.get_last_valid_slot(),
+            last_valid_unix_timestamp_in_seconds: self.get_last_valid_unix_timestamp_in_seconds(),
+            was_deprecated,
+        })
+    }
+
+    fn eq_ignoring_client_id(&self, other: &Self) -> bool {
+        self.redacted() == other.redacted()
+    }
+
+    fn redacted(&self) -> Self {
+        let mut packet = *self;
+        match &mut packet {
+            OrderPacket::PostOnly {
+                client_order_id, ..
+            }
+            | OrderPacket::Limit {
+                client_order_id, ..
+            }
+            | OrderPacket::ImmediateOrCancel {
+                client_order_id, ..
+            } => *client_order_id = 0,
+        }
+        packet
+    }
+
+    fn redacted_display(&self) -> RedactedOrderPacket<'_> {
+        RedactedOrderPacket(self)
+    }
+
+    fn as_resting_order(
+        &self,
+        trader: Pubkey,
+        order_sequence_number: u64,
+    ) -> Option<(FIFOOrderId, PhoenixOrder)> {
+        if self.is_take_only() {
+            return None;
+        }
+        let order_sequence_number = match self.side() {
+            Side::Bid => !order_sequence_number,
+            Side::Ask => order_sequence_number,
+        };
+        Some((
+            FIFOOrderId::new(self.get_price_in_ticks(), order_sequence_number),
+            PhoenixOrder {
+                num_base_lots: self.num_base_lots().as_u64(),
+                maker_id: trader,
+            },
+        ))
+    }
+
+    fn content_hash(&self) -> [u8; 32] {
+        let bytes = self
+            .redacted()
+            .try_to_vec()
+            .expect("Writing to a Vec cannot fail");
+        hash(&bytes).to_bytes()
+    }
+}
+
+/// Displays an order packet as `Variant { field: value, .. }` with the `client_order_id` left out.
+/// Created by `OrderPacketExt::redacted_display`.
+pub struct RedactedOrderPacket<'a>(&'a OrderPacket);
+
+impl std::fmt::Display for RedactedOrderPacket<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields = packet_fields(self.0)
+            .into_iter()
+            .filter(|(name, _)| *name != "client_order_id")
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<_>>();
+        write!(f, "{} {{ {} }}", order_type_name(self.0), fields.join(", "))
+    }
+}
+
+/// Returns the name of the packet's variant, as used in `describe` and audit records.
+fn order_type_name(packet: &OrderPacket) -> &'static str {
+    match packet {
+        OrderPacket::PostOnly { .. } => "PostOnly",
+        OrderPacket::Limit { .. } => "Limit",
+        OrderPacket::ImmediateOrCancel { .. } => "ImmediateOrCancel",
+    }
+}
+
+/// Fields that only exist in the current packet layout. Packets decoded from the deprecated layout
+/// always have these unset.
+const CURRENT_ONLY_FIELDS: [&str; 3] = [
+    "last_valid_slot",
+    "last_valid_unix_timestamp_in_seconds",
+    "fail_silently_on_insufficient_funds",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldDiff {
+    /// The packets are different order types, so no fields are comparable.
+    Variant,
+    /// A field shared by both layouts holds different values.
+    Changed(&'static str),
+    /// A field that only exists in the current layout is set on the new packet.
+    New(&'static str),
+}
+
+fn packet_fields(packet: &OrderPacket) -> Vec<(&'static str, String)> {
+    match packet {
+        OrderPacket::PostOnly {
+            side,
+            price_in_ticks,
+            num_base_lots,
+            client_order_id,
+            reject_post_only,
+            use_only_deposited_funds,
+            last_valid_slot,
+            last_valid_unix_timestamp_in_seconds,
+            fail_silently_on_insufficient_funds,
+        } => vec![
+            ("side", format!("{:?}", side)),
+            ("price_in_ticks", format!("{:?}", price_in_ticks)),
+            ("num_base_lots", format!("{:?}", num_base_lots)),
+            ("client_order_id", format!("{:?}", client_order_id)),
+            ("reject_post_only", format!("{:?}", reject_post_only)),
+            (
+                "use_only_deposited_funds",
+                format!("{:?}", use_only_deposited_funds),
+            ),
+            ("last_valid_slot", format!("{:?}", last_valid_slot)),
+            (
+                "last_valid_unix_timestamp_in_seconds",
+                format!("{:?}", last_valid_unix_timestamp_in_seconds),
+            ),
+            (
+                "fail_silently_on_insufficient_funds",
+                format!("{:?}", fail_silently_on_insufficient_funds),
+            ),
+        ],
+        OrderPacket::Limit {
+            side,
+            price_in_ticks,
+            num_base_lots,
+            self_trade_behavior,
+            match_limit,
+            client_order_id,
+            use_only_deposited_funds,
+            last_valid_slot,
+            last_valid_unix_timestamp_in_seconds,
+            fail_silently_on_insufficient_funds,
+        } => vec![
+            ("side", format!("{:?}", side)),
+            ("price_in_ticks", format!("{:?}", price_in_ticks)),
+            ("num_base_lots", format!("{:?}", num_base_lots)),
+            ("self_trade_behavior", format!("{:?}", self_trade_behavior)),
+            ("match_limit", format!("{:?}", match_limit)),
+            ("client_order_id", format!("{:?}", client_order_id)),
+            (
+                "use_only_deposited_funds",
+                format!("{:?}", use_only_deposited_funds),
+            ),
+            ("last_valid_slot", format!("{:?}", last_valid_slot)),
+            (
+                "last_valid_unix_timestamp_in_seconds",
+                format!("{:?}", last_valid_unix_timestamp_in_seconds),
+            ),
+            (
+                "fail_silently_on_insufficient_funds",
+                format!("{:?}", fail_silently_on_insufficient_funds),
+            ),
+        ],
+        OrderPacket::ImmediateOrCancel {
+            side,
+            price_in_ticks,
+            num_base_lots,
+            num_quote_lots,
+            min_base_lots_to_fill,
+            min_quote_lots_to_fill,
+            self_trade_behavior,
+            match_limit,
+            client_order_id,
+            use_only_deposited_funds,
+            last_valid_slot,
+            last_valid_unix_timestamp_in_seconds,
+        } => vec![
+            ("side", format!("{:?}", side)),
+            ("price_in_ticks", format!("{:?}", price_in_ticks)),
+            ("num_base_lots", format!("{:?}", num_base_lots)),
+            ("num_quote_lots", format!("{:?}", num_quote_lots)),
+            (
+                "min_base_lots_to_fill",
+                format!("{:?}", min_base_lots_to_fill),
+            ),
+            (
+                "min_quote_lots_to_fill",
+                format!("{:?}", min_quote_lots_to_fill),
+            ),
+            ("self_trade_behavior", format!("{:?}", self_trade_behavior)),
+            ("match_limit", format!("{:?}", match_limit)),
+            ("client_order_id", format!("{:?}", client_order_id)),
+            (
+                "use_only_deposited_funds",
+                format!("{:?}", use_only_deposited_funds),
+            ),
+            ("last_valid_slot", format!("{:?}", last_valid_slot)),
+            (
+                "last_valid_unix_timestamp_in_seconds",
+                format!("{:?}", last_valid_unix_timestamp_in_seconds),
+            ),
+        ],
+    }
+}
+
+/// Compares a packet in the current layout against one decoded from the deprecated layout.
+/// Shared fields that differ are reported as `FieldDiff::Changed`, and current-only fields
+/// (the expiration options and `fail_silently_on_insufficient_funds`) that are set on `new`
+/// are reported as `FieldDiff::New`. An empty result means upgrading `deprecated` is lossless.
+pub fn diff_deprecated(new: &OrderPacket, deprecated: &OrderPacket) -> Vec<FieldDiff> {
+    if std::mem::discriminant(new) != std::mem::discriminant(deprecated) {
+        return vec![FieldDiff::Variant];
+    }
+    packet_fields(new)
+        .into_iter()
+        .zip(packet_fields(deprecated))
+        .filter(|((_, new_value), (_, deprecated_value))| new_value != deprecated_value)
+        .map(|((name, _), _)| {
+            if CURRENT_ONLY_FIELDS.contains(&name) {
+                FieldDiff::New(name)
+            } else {
+                FieldDiff::Changed(name)
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "log-decode-errors")]
+fn log_decode_failure(bytes: &[u8], error: &std::io::Error) {
+    const MAX_LOGGED_BYTES: usize = 128;
+    let hex = bytes
+        .iter()
+        .take(MAX_LOGGED_BYTES)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    log::warn!(
+        "Failed to decode order packet (tag: {:?}, len: {}): {}. Raw bytes: {}{}",
+        bytes.first(),
+        bytes.len(),
+        error,
+        hex,
+        if bytes.len() > MAX_LOGGED_BYTES {
+            "..."
+        } else {
+            ""
+        }
+    );
+}
+
+#[cfg(not(feature = "log-decode-errors"))]
+#[inline(always)]
+fn log_decode_failure(_bytes: &[u8], _error: &std::io::Error) {}
+
+/// Decodes an order packet in either the current or the deprecated layout.
+///
+/// The current layout is parsed first, so well-formed current packets are only parsed once.
+/// Only when that fails is the data parsed a second time as the deprecated layout, padded on
+/// the stack rather than copied into a new allocation.
 pub fn decode_order_packet(bytes: &[u8]) -> anyhow::Result<OrderPacket> {
-    let order_packet = match OrderPacket::try_from_slice(bytes) {
-        Ok(order_packet) => order_packet,
-        Err(_) => {
-            let padded_bytes = [bytes, &[0, 0]].concat();
-            OrderPacket::try_from_slice(&padded_bytes)?
+    let error = match OrderPacket::try_from_slice(bytes) {
+        Ok(order_packet) => return Ok(order_packet),
+        Err(e) => e,
+    };
+    let padded_len = bytes.len() + 2;
+    if padded_len > MAX_ORDER_PACKET_LEN {
+        log_decode_failure(bytes, &error);
+        return Err(error.into());
+    }
+    let mut padded_bytes = [0_u8; MAX_ORDER_PACKET_LEN];
+    padded_bytes[..bytes.len()].copy_from_slice(bytes);
+    let order_packet = OrderPacket::try_from_slice(&padded_bytes[..padded_len])
+        .inspect_err(|e| log_decode_failure(bytes, e))?;
+    Ok(order_packet)
+}
+
+/// An order packet decoded from the front of a buffer by `decode_order_packet_prefix`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderPacketPrefix {
+    pub packet: OrderPacket,
+    /// Number of bytes at the front of the buffer the packet was decoded from.
+    pub consumed: usize,
+    /// True if the packet was decoded in the current layout and more data follows it. A
+    /// deprecated-layout packet followed by trailing data can decode the same way, with the start
+    /// of the trailing data read as its missing expiry fields, so in that case `packet` and
+    /// `consumed` are only right if the caller knows the packet uses the current layout.
+    pub ambiguous_layout: bool,
+}
+
+/// Decodes a single order packet from the front of `bytes`, ignoring any data that follows it.
+///
+/// A packet's layout cannot be told from its bytes alone, so the current layout is assumed
+/// whenever it decodes, and the deprecated layout is only recognized when the packet runs to the
+/// end of `bytes`. When the current layout decodes with data left over, the result is flagged
+/// with `ambiguous_layout`. Callers that know each packet's length should decode the exact slice
+/// with `decode_order_packet` instead, as `decode_order_packets` does.
+pub fn decode_order_packet_prefix(bytes: &[u8]) -> anyhow::Result<OrderPacketPrefix> {
+    let mut remaining = bytes;
+    match OrderPacket::deserialize(&mut remaining) {
+        Ok(packet) => Ok(OrderPacketPrefix {
+            packet,
+            consumed: bytes.len() - remaining.len(),
+            ambiguous_layout: !remaining.is_empty(),
+        }),
+        Err(_) => Ok(OrderPacketPrefix {
+            packet: decode_order_packet(bytes)?,
+            consumed: bytes.len(),
+            ambiguous_layout: false,
+        }),
+    }
+}
+
+/// An order packet decoded by `decode_order_packet_lenient`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedOrderPacket {
+    Known(OrderPacket),
+    /// A packet whose leading tag is not a variant this SDK knows about, such as one added in a
+    /// newer version of Phoenix. The raw bytes, starting with the tag, are kept so callers can
+    /// record and skip it.
+    Unknown {
+        tag: u8,
+        raw: Vec<u8>,
+    },
+}
+
+/// Same as `decode_order_packet`, but a packet whose leading tag is not a known `OrderPacket`
+/// variant decodes to `DecodedOrderPacket::Unknown` instead of failing. Packets with a known tag
+/// that fail to decode, and empty input, are still errors.
+pub fn decode_order_packet_lenient(bytes: &[u8]) -> anyhow::Result<DecodedOrderPacket> {
+    match bytes.first() {
+        None => Err(anyhow::anyhow!("Cannot decode an empty order packet")),
+        Some(&tag) if tag > IMMEDIATE_OR_CANCEL_TAG => Ok(DecodedOrderPacket::Unknown {
+            tag,
+            raw: bytes.to_vec(),
+        }),
+        Some(_) => decode_order_packet(bytes).map(DecodedOrderPacket::Known),
+    }
+}
+
+/// Version byte for a packet body in the deprecated layout. Versions start at 2 because the first
+/// byte of an unversioned body is the `Side`, which is always 0 or 1.
+pub const PACKET_VERSION_DEPRECATED: u8 = 2;
+/// Version byte for a packet body in the current layout.
+pub const PACKET_VERSION_CURRENT: u8 = 3;
+
+/// Encodes a packet as `[tag, version, ..body]` using the current layout.
+pub fn encode_order_packet_versioned(packet: &OrderPacket) -> Vec<u8> {
+    let bytes = packet.try_to_vec().expect("Writing to a Vec cannot fail");
+    [&bytes[..1], &[PACKET_VERSION_CURRENT], &bytes[1..]].concat()
+}
+
+/// Decodes a packet written as `[tag, version, ..body]`, using the version to pick the layout of
+/// the body. Data without a version byte is decoded with `decode_order_packet`, which infers the
+/// layout from the length.
+pub fn decode_order_packet_versioned(bytes: &[u8]) -> anyhow::Result<OrderPacket> {
+    let (tag, version, body) = match bytes {
+        [tag, version, body @ ..] if *version >= PACKET_VERSION_DEPRECATED => {
+            (*tag, *version, body)
+        }
+        _ => return decode_order_packet(bytes),
+    };
+    let order_packet = match version {
+        PACKET_VERSION_DEPRECATED => {
+            OrderPacket::try_from_slice(&[&[tag], body, &[0, 0]].concat())?
+        }
+        PACKET_VERSION_CURRENT => OrderPacket::try_from_slice(&[&[tag], body].concat())?,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unsupported order packet version {}",
+                version
+            ))
         }
     };
     Ok(order_packet)
 }
 
+/// Encodes `packets` into one buffer that `decode_order_packets` splits back apart. Each packet
+/// is framed as a little-endian `u32` length followed by that many bytes, which start with the
+/// packet's tag. Known packets are written in the current layout and unknown packets as their
+/// raw bytes, so every packet round-trips.
+pub fn encode_order_packets(packets: &[DecodedOrderPacket]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for packet in packets {
+        let frame = match packet {
+            DecodedOrderPacket::Known(packet) => {
+                packet.try_to_vec().expect("Writing to a Vec cannot fail")
+            }
+            DecodedOrderPacket::Unknown { raw, .. } => raw.clone(),
+        };
+        frame
+            .serialize(&mut bytes)
+            .expect("Writing to a Vec cannot fail");
+    }
+    bytes
+}
+
+/// Decodes the length-prefixed packets written by `encode_order_packets`. Each packet is decoded
+/// from exactly the bytes its length prefix covers with `decode_order_packet_lenient`, so packets
+/// in the deprecated layout are recognized anywhere in the batch, and unknown packets are kept.
+/// Fails if a length prefix or packet is truncated, or if a packet with a known tag does not
+/// decode.
+pub fn decode_order_packets(bytes: &[u8]) -> anyhow::Result<Vec<DecodedOrderPacket>> {
+    let mut packets = vec![];
+    let mut remaining = bytes;
+    while !remaining.is_empty() {
+        let frame = Vec::<u8>::deserialize(&mut remaining)?;
+        packets.push(decode_order_packet_lenient(&frame)?);
+    }
+    Ok(packets)
+}
+
+/// Builds `levels` limit orders of `base_lots_per_level` base lots on `side`, starting at
+/// `best_price_in_ticks` and stepping `tick_step` ticks away from the spread per level: down for
+/// bids and up for asks. Level `i` gets client order id `base_client_order_id + i`. A `tick_step`
+/// of 0 is treated as 1, and the grid stops early rather than pricing a level at 0 ticks or past
+/// `u64::MAX`.
+pub fn build_quote_grid(
+    side: Side,
+    best_price_in_ticks: u64,
+    tick_step: u64,
+    levels: usize,
+    base_lots_per_level: u64,
+    base_client_order_id: u128,
+) -> Vec<OrderPacket> {
+    let tick_step = tick_step.max(1);
+    (0..levels)
+        .map_while(|level| {
+            let offset_in_ticks = tick_step.checked_mul(level as u64)?;
+            let price_in_ticks = match side {
+                Side::Bid => best_price_in_ticks.checked_sub(offset_in_ticks)?,
+                Side::Ask => best_price_in_ticks.checked_add(offset_in_ticks)?,
+            };
+            if price_in_ticks == 0 {
+                return None;
+            }
+            Some(OrderPacket::new_limit_order_default_with_client_order_id(
+                side,
+                price_in_ticks,
+                base_lots_per_level,
+                base_client_order_id.wrapping_add(level as u128),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the index pairs `(i, j)`, with `i < j`, of packets in `packets` that are on opposite
+/// sides and whose prices cross or touch, meaning the buy is priced at or above the sell and the
+/// two orders could trade with each other. IOC packets without a limit price are treated as
+/// market orders, so they cross every packet on the other side.
+pub fn detect_self_cross(packets: &[OrderPacket]) -> Vec<(usize, usize)> {
+    let mut crossing = vec![];
+    for (i, first) in packets.iter().enumerate() {
+        for (j, second) in packets.iter().enumerate().skip(i + 1) {
+            let (bid, ask) = match (first.side(), second.side()) {
+                (Side::Bid, Side::Ask) => (first, second),
+                (Side::Ask, Side::Bid) => (second, first),
+                _ => continue,
+            };
+            if bid.get_price_in_ticks() >= ask.get_price_in_ticks() {
+                crossing.push((i, j));
+            }
+        }
+    }
+    crossing
+}
+
 #[test]
 fn test_decode_order_packet() {
     let post_only_op = OrderPacket::new_post_only_default(phoenix::state::Side::Ask, 10000, 10);
@@ -48,4 +818,560 @@ fn test_decode_order_packet() {
     let decoded_inferred = decode_order_packet(&bytes[..bytes.len() - 2]).unwrap();
     assert_eq!(ioc_op, decoded_normal);
     assert_eq!(decoded_normal, decoded_inferred);
+
+    let ioc_op = OrderPacket::new_ioc(
+        phoenix::state::Side::Ask,
+        Some(10000),
+        10,
+        0,
+        0,
+        0,
+        phoenix::state::SelfTradeBehavior::Abort,
+        Some(5),
+        0,
+        false,
+        Some(100),
+        Some(1_700_000_000),
+    );
+    let bytes = ioc_op.try_to_vec().unwrap();
+    assert_eq!(bytes.len(), MAX_ORDER_PACKET_LEN);
+    assert_eq!(decode_order_packet(&bytes).unwrap(), ioc_op);
+
+    assert!(decode_order_packet(&[0; MAX_ORDER_PACKET_LEN]).is_err());
+}
+
+#[test]
+fn test_decode_order_packet_prefix() {
+    let limit_op = OrderPacket::new_limit_order_default(phoenix::state::Side::Bid, 10000, 10);
+    let bytes = limit_op.try_to_vec().unwrap();
+    let with_trailing_bytes = [bytes.as_slice(), &[1, 2, 3, 4]].concat();
+    let prefix = decode_order_packet_prefix(&with_trailing_bytes).unwrap();
+    assert_eq!(prefix.packet, limit_op);
+    assert_eq!(prefix.consumed, bytes.len());
+    assert!(prefix.ambiguous_layout);
+
+    let prefix = decode_order_packet_prefix(&bytes).unwrap();
+    assert_eq!(prefix.consumed, bytes.len());
+    assert!(!prefix.ambiguous_layout);
+
+    let deprecated = &bytes[..bytes.len() - 2];
+    let prefix = decode_order_packet_prefix(deprecated).unwrap();
+    assert_eq!(prefix.packet, limit_op);
+    assert_eq!(prefix.consumed, deprecated.len());
+    assert!(!prefix.ambiguous_layout);
+
+    // A deprecated packet followed by trailing data reads the first trailing bytes as its expiry
+    // fields, which can't be detected, so the result is flagged
+    let with_trailing_bytes = [deprecated, &[0, 0, 9, 9]].concat();
+    let prefix = decode_order_packet_prefix(&with_trailing_bytes).unwrap();
+    assert_eq!(prefix.consumed, deprecated.len() + 2);
+    assert!(prefix.ambiguous_layout);
+
+    assert!(decode_order_packet_prefix(&bytes[..10]).is_err());
+}
+
+/// Micro-benchmark comparing `decode_order_packet` to the previous fallback, which copied every
+/// deprecated packet into a new allocation before parsing it again. Run it with
+/// `cargo test --release -p phoenix-sdk-core bench_decode_order_packet -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_decode_order_packet() {
+    use std::time::Instant;
+
+    fn decode_with_concat(bytes: &[u8]) -> anyhow::Result<OrderPacket> {
+        match OrderPacket::try_from_slice(bytes) {
+            Ok(order_packet) => Ok(order_packet),
+            Err(_) => Ok(OrderPacket::try_from_slice(&[bytes, &[0, 0]].concat())?),
+        }
+    }
+
+    let packets = (0..100_000_u64)
+        .map(|i| {
+            let side = if i % 4 < 2 { Side::Bid } else { Side::Ask };
+            let bytes = OrderPacket::new_limit_order_default(side, 10_000 + i % 100, 1 + i % 10)
+                .try_to_vec()
+                .unwrap();
+            // Half of the packets are in the deprecated layout, which takes the fallback path
+            if i % 2 == 0 {
+                bytes[..bytes.len() - 2].to_vec()
+            } else {
+                bytes
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let time = |decode: &dyn Fn(&[u8]) -> anyhow::Result<OrderPacket>| {
+        let start = Instant::now();
+        let decoded = packets
+            .iter()
+            .map(|bytes| decode(bytes).unwrap())
+            .collect::<Vec<_>>();
+        (start.elapsed(), decoded)
+    };
+    let (concat_elapsed, concat_decoded) = time(&decode_with_concat);
+    let (elapsed, decoded) = time(&decode_order_packet);
+    assert_eq!(decoded, concat_decoded);
+    println!(
+        "decoded {} packets: {:?} with concat, {:?} with stack padding",
+        packets.len(),
+        concat_elapsed,
+        elapsed
+    );
+}
+
+#[test]
+fn test_build_quote_grid() {
+    let grid = build_quote_grid(Side::Bid, 10000, 5, 3, 10, 100);
+    assert_eq!(
+        grid,
+        vec![
+            OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 10000, 10, 100),
+            OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 9995, 10, 101),
+            OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 9990, 10, 102),
+        ]
+    );
+    let prices = |grid: Vec<OrderPacket>| {
+        grid.iter()
+            .map(|packet| packet.get_price_in_ticks().as_u64())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        prices(build_quote_grid(Side::Ask, 10000, 5, 3, 10, 0)),
+        vec![10000, 10005, 10010]
+    );
+    // Zero steps are one tick, and bids stop before reaching a price of 0
+    assert_eq!(
+        prices(build_quote_grid(Side::Bid, 3, 0, 5, 10, 0)),
+        vec![3, 2, 1]
+    );
+    assert_eq!(
+        prices(build_quote_grid(Side::Ask, u64::MAX - 1, 1, 5, 10, 0)),
+        vec![u64::MAX - 1, u64::MAX]
+    );
+    assert!(build_quote_grid(Side::Bid, 10000, 5, 0, 10, 0).is_empty());
+    // The grid never crosses itself
+    let mut grid = build_quote_grid(Side::Bid, 9999, 1, 3, 10, 0);
+    grid.extend(build_quote_grid(Side::Ask, 10000, 1, 3, 10, 3));
+    assert!(detect_self_cross(&grid).is_empty());
+}
+
+#[test]
+fn test_detect_self_cross() {
+    let packets = [
+        OrderPacket::new_limit_order_default(Side::Bid, 10000, 10),
+        OrderPacket::new_limit_order_default(Side::Ask, 10001, 10),
+        OrderPacket::new_post_only_default(Side::Ask, 10000, 10),
+        OrderPacket::new_limit_order_default(Side::Bid, 9990, 10),
+        OrderPacket::new_ioc_sell_with_limit_price(
+            9000,
+            10,
+            phoenix::state::SelfTradeBehavior::Abort,
+            None,
+            0,
+            false,
+        ),
+    ];
+    // The 10000 bid touches the 10000 post-only ask, and the IOC sell at 9000 crosses both bids
+    assert_eq!(detect_self_cross(&packets), vec![(0, 2), (0, 4), (3, 4)]);
+
+    assert!(detect_self_cross(&packets[..2]).is_empty());
+    assert!(detect_self_cross(&[]).is_empty());
+}
+
+#[test]
+fn test_decode_order_packet_lenient() {
+    let packet = OrderPacket::new_limit_order_default(Side::Bid, 10000, 10);
+    let bytes = packet.try_to_vec().unwrap();
+    assert_eq!(
+        decode_order_packet_lenient(&bytes).unwrap(),
+        DecodedOrderPacket::Known(packet)
+    );
+
+    let mut unknown = bytes.clone();
+    unknown[0] = IMMEDIATE_OR_CANCEL_TAG + 1;
+    assert!(decode_order_packet(&unknown).is_err());
+    assert_eq!(
+        decode_order_packet_lenient(&unknown).unwrap(),
+        DecodedOrderPacket::Unknown {
+            tag: IMMEDIATE_OR_CANCEL_TAG + 1,
+            raw: unknown.clone(),
+        }
+    );
+
+    // Known tags must still decode
+    assert!(decode_order_packet_lenient(&bytes[..10]).is_err());
+    assert!(decode_order_packet_lenient(&[]).is_err());
+}
+
+#[test]
+fn test_encode_order_packets() {
+    use phoenix::state::Side;
+    let post_only = OrderPacket::new_post_only_default(Side::Ask, 10000, 10);
+    let limit = OrderPacket::new_limit_order_default(Side::Bid, 9990, 20);
+    let ioc = OrderPacket::new_ioc_buy_with_slippage(100000, 5);
+    let unknown_tag = IMMEDIATE_OR_CANCEL_TAG + 1;
+    let packets = vec![
+        DecodedOrderPacket::Known(post_only),
+        DecodedOrderPacket::Unknown {
+            tag: unknown_tag,
+            raw: vec![unknown_tag, 1, 2, 3],
+        },
+        DecodedOrderPacket::Known(limit),
+        DecodedOrderPacket::Known(ioc),
+    ];
+    let bytes = encode_order_packets(&packets);
+    // A 4 byte length prefix per packet, plus the unknown packet's 4 raw bytes
+    assert_eq!(
+        bytes.len(),
+        4 * packets.len()
+            + post_only.serialized_len()
+            + 4
+            + limit.serialized_len()
+            + ioc.serialized_len()
+    );
+    assert_eq!(decode_order_packets(&bytes).unwrap(), packets);
+    assert!(decode_order_packets(&[]).unwrap().is_empty());
+    assert!(decode_order_packets(&bytes[..bytes.len() - 10]).is_err());
+    assert!(decode_order_packets(&bytes[..2]).is_err());
+
+    // A deprecated packet in the middle of a batch is bounded by its length prefix, so the next
+    // packet's tag is not read as one of its missing optional fields
+    let limit_bytes = limit.try_to_vec().unwrap();
+    let mut bytes = vec![];
+    for frame in [
+        post_only.try_to_vec().unwrap(),
+        limit_bytes[..limit_bytes.len() - 2].to_vec(),
+        ioc.try_to_vec().unwrap(),
+    ] {
+        frame.serialize(&mut bytes).unwrap();
+    }
+    assert_eq!(
+        decode_order_packets(&bytes).unwrap(),
+        [post_only, limit, ioc].map(DecodedOrderPacket::Known)
+    );
+}
+
+#[test]
+fn test_decode_order_packet_versioned() {
+    use phoenix::state::Side;
+    let limit_op = OrderPacket::new_limit_order_default(Side::Bid, 10000, 10);
+    let versioned = encode_order_packet_versioned(&limit_op);
+    assert_eq!(versioned[1], PACKET_VERSION_CURRENT);
+    assert_eq!(decode_order_packet_versioned(&versioned).unwrap(), limit_op);
+
+    let mut deprecated = versioned[..versioned.len() - 2].to_vec();
+    deprecated[1] = PACKET_VERSION_DEPRECATED;
+    assert_eq!(
+        decode_order_packet_versioned(&deprecated).unwrap(),
+        limit_op
+    );
+
+    // Unversioned data falls back to the length heuristic
+    let bytes = limit_op.try_to_vec().unwrap();
+    assert_eq!(decode_order_packet_versioned(&bytes).unwrap(), limit_op);
+    assert_eq!(
+        decode_order_packet_versioned(&bytes[..bytes.len() - 2]).unwrap(),
+        limit_op
+    );
+
+    let mut unknown_version = versioned.clone();
+    unknown_version[1] = PACKET_VERSION_CURRENT + 1;
+    assert!(decode_order_packet_versioned(&unknown_version).is_err());
+}
+
+#[test]
+fn test_diff_deprecated() {
+    let limit_op = OrderPacket::new_limit_order_default(phoenix::state::Side::Bid, 10000, 10);
+    let bytes = limit_op.try_to_vec().unwrap();
+    let deprecated = decode_order_packet(&bytes[..bytes.len() - 2]).unwrap();
+    assert!(diff_deprecated(&limit_op, &deprecated).is_empty());
+
+    let mut new_op = OrderPacket::new_limit_order_default(phoenix::state::Side::Bid, 10001, 10);
+    if let OrderPacket::Limit {
+        last_valid_slot, ..
+    } = &mut new_op
+    {
+        *last_valid_slot = Some(100);
+    }
+    assert_eq!(
+        diff_deprecated(&new_op, &deprecated),
+        vec![
+            FieldDiff::Changed("price_in_ticks"),
+            FieldDiff::New("last_valid_slot")
+        ]
+    );
+
+    let post_only_op = OrderPacket::new_post_only_default(phoenix::state::Side::Bid, 10000, 10);
+    assert_eq!(
+        diff_deprecated(&post_only_op, &deprecated),
+        vec![FieldDiff::Variant]
+    );
+}
+
+#[test]
+fn test_describe() {
+    use phoenix::state::Side;
+    let metadata = crate::test_support::sol_usdc_metadata();
+    assert_eq!(
+        OrderPacket::new_limit_order_default(Side::Bid, 22719, 10).describe(&metadata),
+        "Limit Bid 0.01 @ 22.719"
+    );
+    assert_eq!(
+        OrderPacket::new_post_only_default(Side::Ask, 22720, 1000).describe(&metadata),
+        "PostOnly Ask 1 @ 22.72"
+    );
+    assert_eq!(
+        OrderPacket::new_ioc(
+            Side::Ask,
+            None,
+            10,
+            0,
+            0,
+            0,
+            phoenix::state::SelfTradeBehavior::Abort,
+            None,
+            0,
+            false,
+            None,
+            None
+        )
+        .describe(&metadata),
+        "ImmediateOrCancel Ask 0.01 @ market"
+    );
+}
+
+#[test]
+fn test_to_audit_record() {
+    use phoenix::state::Side;
+    let metadata = crate::test_support::sol_usdc_metadata();
+    let record = OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 22719, 10, 7)
+        .to_audit_record(&metadata, true)
+        .unwrap();
+    assert_eq!(
+        record,
+        OrderAuditRecord {
+            order_type: "Limit".to_string(),
+            side: Side::Bid,
+            price_in_ticks: Some(22719),
+            price_ui: Some(22.719),
+            num_base_lots: 10,
+            base_ui: 0.01,
+            num_quote_lots: 0,
+            quote_ui: 0.0,
+            client_order_id: 7,
+            self_trade_behavior: Some(SelfTradeBehavior::CancelProvide),
+            last_valid_slot: None,
+            last_valid_unix_timestamp_in_seconds: None,
+            was_deprecated: true,
+        }
+    );
+    assert_eq!(
+        OrderAuditRecord::try_from_slice(&record.try_to_vec().unwrap()).unwrap(),
+        record
+    );
+
+    let record = OrderPacket::new_ioc_buy_with_slippage(100_000, 10)
+        .to_audit_record(&metadata, false)
+        .unwrap();
+    assert_eq!(record.order_type, "ImmediateOrCancel");
+    assert_eq!(record.price_in_ticks, None);
+    assert_eq!(record.price_ui, None);
+    assert_eq!(record.num_quote_lots, 100_000);
+    assert_eq!(record.quote_ui, 0.1);
+
+    let record = OrderPacket::new_post_only_default(Side::Ask, 22720, 1000)
+        .to_audit_record(&metadata, false)
+        .unwrap();
+    assert_eq!(record.self_trade_behavior, None);
+    assert_eq!(record.base_ui, 1.0);
+
+    // Zeroed metadata turns every UI amount into NaN, which Borsh cannot serialize
+    assert!(OrderPacket::new_limit_order_default(Side::Bid, 22719, 10)
+        .to_audit_record(&MarketMetadata::default(), false)
+        .is_err());
+}
+
+#[test]
+fn test_eq_ignoring_client_id() {
+    let packet = OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 10000, 10, 1);
+    let resubmitted =
+        OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 10000, 10, 2);
+    assert_ne!(packet, resubmitted);
+    assert!(packet.eq_ignoring_client_id(&resubmitted));
+
+    let resized =
+        OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 10000, 11, 1);
+    assert!(!packet.eq_ignoring_client_id(&resized));
+    let post_only =
+        OrderPacket::new_post_only_default_with_client_order_id(Side::Bid, 10000, 10, 1);
+    assert!(!packet.eq_ignoring_client_id(&post_only));
+}
+
+#[test]
+fn test_as_resting_order() {
+    let trader = Pubkey::new_unique();
+    let packet = OrderPacket::new_limit_order_default(Side::Ask, 100, 10);
+    let (order_id, order) = packet.as_resting_order(trader, 7).unwrap();
+    assert_eq!(order_id, FIFOOrderId::new_from_untyped(100, 7));
+    assert_eq!(order.num_base_lots, 10);
+    assert_eq!(order.maker_id, trader);
+
+    // Bid ids sort the earlier sequence number first at the same price
+    let packet = OrderPacket::new_post_only_default(Side::Bid, 100, 10);
+    let (first, _) = packet.as_resting_order(trader, 7).unwrap();
+    let (second, _) = packet.as_resting_order(trader, 8).unwrap();
+    assert_eq!(
+        Side::from_order_sequence_number(first.order_sequence_number),
+        Side::Bid
+    );
+    assert!(first < second);
+
+    let packet =
+        OrderPacket::new_ioc_by_lots(Side::Bid, 100, 10, SelfTradeBehavior::Abort, None, 0, false);
+    assert!(packet.as_resting_order(trader, 7).is_none());
+}
+
+#[test]
+fn test_content_hash() {
+    let packet = OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 100, 10, 1);
+    let remapped = OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 100, 10, 2);
+    assert_eq!(packet.content_hash(), remapped.content_hash());
+    assert_eq!(
+        packet.content_hash(),
+        hash(&packet.redacted().try_to_vec().unwrap()).to_bytes()
+    );
+
+    let resized = OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 100, 11, 1);
+    let post_only = OrderPacket::new_post_only_default_with_client_order_id(Side::Bid, 100, 10, 1);
+    assert_ne!(packet.content_hash(), resized.content_hash());
+    assert_ne!(packet.content_hash(), post_only.content_hash());
+}
+
+#[test]
+fn test_redacted() {
+    let packet =
+        OrderPacket::new_limit_order_default_with_client_order_id(Side::Bid, 10000, 10, 123456789);
+    let redacted = packet.redacted();
+    assert_eq!(redacted.client_order_id(), 0);
+    assert!(redacted.eq_ignoring_client_id(&packet));
+
+    let display = packet.redacted_display().to_string();
+    assert!(display.starts_with("Limit { side: Bid, "));
+    assert!(display.contains("num_base_lots"));
+    assert!(!display.contains("client_order_id"));
+    assert!(!display.contains("123456789"));
+}
+
+#[test]
+fn test_required_account_count() {
+    use phoenix::state::Side;
+    let limit_op = OrderPacket::new_limit_order_default(Side::Bid, 10000, 10);
+    assert_eq!(limit_op.required_account_count(), 10);
+    let ioc_op = OrderPacket::new_ioc_buy_with_slippage(100, 10);
+    assert_eq!(ioc_op.required_account_count(), 9);
+    let deposited_funds_op = OrderPacket::new_post_only(Side::Ask, 10000, 10, 0, false, true);
+    assert_eq!(deposited_funds_op.required_account_count(), 5);
+}
+
+#[test]
+fn test_canonicalize() {
+    use phoenix::state::{SelfTradeBehavior, Side};
+    let ioc = |side: Side, price_in_ticks: Option<u64>, match_limit: Option<u64>| {
+        OrderPacket::new_ioc(
+            side,
+            price_in_ticks,
+            10,
+            0,
+            0,
+            0,
+            SelfTradeBehavior::Abort,
+            match_limit,
+            0,
+            false,
+            None,
+            None,
+        )
+    };
+    assert_eq!(
+        ioc(Side::Bid, Some(u64::MAX), Some(u64::MAX)).canonicalize(),
+        ioc(Side::Bid, None, None)
+    );
+    assert_eq!(
+        ioc(Side::Ask, Some(0), None).canonicalize(),
+        ioc(Side::Ask, None, None)
+    );
+    // A zero tick bid never matches, so it is not a market order
+    assert_eq!(
+        ioc(Side::Bid, Some(0), None).canonicalize(),
+        ioc(Side::Bid, Some(0), None)
+    );
+    assert_eq!(
+        ioc(Side::Bid, Some(10000), Some(3)).canonicalize(),
+        ioc(Side::Bid, Some(10000), Some(3))
+    );
+
+    let mut limit = OrderPacket::new_limit_order_default(Side::Bid, 10000, 10);
+    let expected = limit;
+    if let OrderPacket::Limit { match_limit, .. } = &mut limit {
+        *match_limit = Some(u64::MAX);
+    }
+    assert_ne!(limit, expected);
+    assert_eq!(limit.canonicalize(), expected);
+
+    let post_only = OrderPacket::new_post_only_default(Side::Ask, 10000, 10);
+    assert_eq!(post_only.canonicalize(), post_only);
+}
+
+#[test]
+fn test_order_packet_tag() {
+    use phoenix::state::Side;
+    let packets = [
+        OrderPacket::new_post_only_default(Side::Ask, 10000, 10),
+        OrderPacket::new_limit_order_default(Side::Bid, 9990, 20),
+        OrderPacket::new_ioc_buy_with_slippage(100000, 5),
+    ];
+    for packet in packets.iter() {
+        assert_eq!(order_packet_tag(packet), packet.try_to_vec().unwrap()[0]);
+    }
+}
+
+#[test]
+fn test_serialized_len() {
+    let post_only_op = OrderPacket::new_post_only_default(phoenix::state::Side::Bid, 10000, 10);
+    assert_eq!(post_only_op.serialized_len(), POST_ONLY_CURRENT_LEN);
+    assert_eq!(
+        post_only_op.try_to_vec().unwrap().len(),
+        post_only_op.serialized_len()
+    );
+
+    let limit_op = OrderPacket::new_limit_order_default(phoenix::state::Side::Bid, 10000, 10);
+    assert_eq!(limit_op.serialized_len(), LIMIT_CURRENT_LEN);
+    assert_eq!(
+        limit_op.try_to_vec().unwrap().len(),
+        limit_op.serialized_len()
+    );
+
+    let ioc_op = OrderPacket::new_ioc_buy_with_slippage(100, 10);
+    assert_eq!(ioc_op.serialized_len(), IMMEDIATE_OR_CANCEL_CURRENT_LEN);
+    assert_eq!(ioc_op.try_to_vec().unwrap().len(), ioc_op.serialized_len());
+
+    let ioc_op = OrderPacket::new_ioc(
+        phoenix::state::Side::Ask,
+        Some(10000),
+        10,
+        0,
+        0,
+        0,
+        phoenix::state::SelfTradeBehavior::Abort,
+        Some(5),
+        0,
+        false,
+        Some(100),
+        Some(1_700_000_000),
+    );
+    assert_eq!(
+        ioc_op.serialized_len(),
+        IMMEDIATE_OR_CANCEL_CURRENT_LEN + 4 * OPTION_PAYLOAD_LEN
+    );
+    assert_eq!(ioc_op.try_to_vec().unwrap().len(), ioc_op.serialized_len());
 }
@@ -0,0 +1,105 @@
+//! Exact conversions between raw atom and lot quantities and UI amounts, done in `Decimal` so
+//! they don't pick up `f64` rounding error.
+use anyhow::anyhow;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+
+/// Converts a number of atoms into an exact UI amount for a token with `decimals` decimals.
+/// Unlike the `f64` conversions this never loses precision, and only fails if `decimals` is
+/// larger than `Decimal` can represent.
+pub fn to_ui_amount_decimal(atoms: u64, decimals: u32) -> anyhow::Result<Decimal> {
+    Decimal::try_from_i128_with_scale(atoms as i128, decimals).map_err(|e| {
+        anyhow!(
+            "Cannot represent {} atoms with {} decimals: {}",
+            atoms,
+            decimals,
+            e
+        )
+    })
+}
+
+/// Converts a UI amount into atoms for a token with `decimals` decimals, failing if the amount
+/// is negative, has more precision than one atom, or does not fit in a `u64`.
+pub fn from_ui_amount_decimal(ui_amount: Decimal, decimals: u32) -> anyhow::Result<u64> {
+    let atoms = 10_u64
+        .checked_pow(decimals)
+        .and_then(|atoms_per_unit| ui_amount.checked_mul(Decimal::from(atoms_per_unit)))
+        .ok_or_else(|| anyhow!("{} with {} decimals overflows", ui_amount, decimals))?;
+    if !atoms.fract().is_zero() {
+        return Err(anyhow!(
+            "{} is not a whole number of atoms with {} decimals",
+            ui_amount,
+            decimals
+        ));
+    }
+    atoms
+        .to_u64()
+        .ok_or_else(|| anyhow!("{} atoms does not fit in a u64", atoms))
+}
+
+/// Returns the whole numbers of base lots on either side of `ui_size` base units, as
+/// `(floor_lots, ceil_lots)`. The two are equal when `ui_size` is already a whole number of lots.
+/// The UI size is read as the shortest decimal that round-trips through the `f64`, so e.g. `0.3`
+/// is exactly 0.3 units rather than the nearest binary fraction. Negative, non-finite, and
+/// unrepresentable sizes, or a lot size of 0, give `(0, 0)`, and sizes past `u64::MAX` lots
+/// saturate.
+pub fn representable_base_lots(
+    ui_size: f64,
+    base_lot_size_in_atoms: u64,
+    base_decimals: u32,
+) -> (u64, u64) {
+    let lots = Decimal::from_f64(ui_size)
+        .filter(|ui_size| !ui_size.is_sign_negative())
+        .zip(10_u64.checked_pow(base_decimals))
+        .filter(|_| base_lot_size_in_atoms > 0)
+        .and_then(|(ui_size, atoms_per_unit)| {
+            ui_size
+                .checked_mul(Decimal::from(atoms_per_unit))?
+                .checked_div(Decimal::from(base_lot_size_in_atoms))
+        });
+    match lots {
+        Some(lots) => (
+            lots.floor().to_u64().unwrap_or(u64::MAX),
+            lots.ceil().to_u64().unwrap_or(u64::MAX),
+        ),
+        None => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_ui_amount_decimal() {
+        // Large enough that an f64 cannot represent every atom
+        let atoms = 9_007_199_254_740_993;
+        let ui_amount = to_ui_amount_decimal(atoms, 9).unwrap();
+        assert_eq!(ui_amount, Decimal::from_str("9007199.254740993").unwrap());
+        assert_eq!(from_ui_amount_decimal(ui_amount, 9).unwrap(), atoms);
+
+        assert_eq!(
+            from_ui_amount_decimal(Decimal::from_str("1.5").unwrap(), 6).unwrap(),
+            1_500_000
+        );
+        assert!(from_ui_amount_decimal(Decimal::from_str("0.0000001").unwrap(), 6).is_err());
+        assert!(from_ui_amount_decimal(Decimal::from_str("-1").unwrap(), 6).is_err());
+        assert!(from_ui_amount_decimal(Decimal::from(u64::MAX), 6).is_err());
+        assert!(to_ui_amount_decimal(1, 29).is_err());
+    }
+
+    #[test]
+    fn test_representable_base_lots() {
+        // SOL has 9 decimals, and a base lot of 1_000_000 atoms is 0.001 SOL
+        assert_eq!(representable_base_lots(1.2345, 1_000_000, 9), (1234, 1235));
+        assert_eq!(representable_base_lots(0.3, 1_000_000, 9), (300, 300));
+        assert_eq!(representable_base_lots(0.0, 1_000_000, 9), (0, 0));
+        assert_eq!(representable_base_lots(0.0001, 1_000_000, 9), (0, 1));
+        assert_eq!(representable_base_lots(-1.0, 1_000_000, 9), (0, 0));
+        assert_eq!(representable_base_lots(f64::NAN, 1_000_000, 9), (0, 0));
+        assert_eq!(representable_base_lots(1.0, 0, 9), (0, 0));
+        // Decimals are not truncated to a u8, where 265 would wrap around to 9
+        assert_eq!(representable_base_lots(1.0, 1_000_000, 265), (0, 0));
+    }
+}
@@ -33,7 +33,7 @@ use std::{
     ops::{Div, Rem},
 };
 
-use crate::{market_event::Fill, orderbook::Orderbook};
+use crate::{market_event::Fill, orderbook::Orderbook, quantities_ext::representable_base_lots};
 
 const AUDIT_LOG_HEADER_LEN: usize = 92;
 
@@ -242,6 +242,48 @@ impl MarketMetadata {
             / (self.quote_atoms_per_quote_unit as f64 * self.raw_base_units_per_base_unit as f64)
     }
 
+    /// Given a side, price in ticks, and number of base lots, returns the lots locked by posting
+    /// that order: quote lots (rounded up) for a bid, or base lots for an ask.
+    pub fn capital_required(&self, side: Side, price_in_ticks: u64, num_base_lots: u64) -> u64 {
+        match side {
+            Side::Bid => {
+                let quote_atoms = num_base_lots as u128
+                    * price_in_ticks as u128
+                    * self.tick_size_in_quote_atoms_per_base_unit as u128;
+                let quote_atoms_per_base_unit_per_quote_lot = self.num_base_lots_per_base_unit
+                    as u128
+                    * self.quote_atoms_per_quote_lot as u128;
+                let quote_lots = quote_atoms.div_ceil(quote_atoms_per_base_unit_per_quote_lot);
+                u64::try_from(quote_lots).unwrap_or(u64::MAX)
+            }
+            Side::Ask => num_base_lots,
+        }
+    }
+
+    /// Given two prices in quote units per raw base unit (represented as floats), returns the number
+    /// of ticks between them. Each price is rounded to the nearest tick first, so float error in
+    /// the inputs does not shift the result by a tick.
+    pub fn ticks_between_ui_prices(&self, low: f64, high: f64) -> u64 {
+        let to_nearest_tick = |price: f64| {
+            ((price
+                * self.raw_base_units_per_base_unit as f64
+                * self.quote_atoms_per_quote_unit as f64)
+                / self.tick_size_in_quote_atoms_per_base_unit as f64)
+                .round() as u64
+        };
+        to_nearest_tick(high).saturating_sub(to_nearest_tick(low))
+    }
+
+    /// Given a size in raw base units (represented as a float), returns the whole numbers of base lots
+    /// on either side of it as `(floor_lots, ceil_lots)`. See `quantities_ext::representable_base_lots`.
+    pub fn representable_base_lots(&self, raw_base_units: f64) -> (u64, u64) {
+        representable_base_lots(
+            raw_base_units,
+            self.base_atoms_per_base_lot,
+            self.base_decimals,
+        )
+    }
+
     /// Returns the base lot size in raw base units (as a float)
     pub fn raw_base_units_per_base_lot(&self) -> f64 {
         self.base_atoms_per_base_lot as f64 / self.base_atoms_per_raw_base_unit as f64
@@ -435,6 +477,40 @@ impl SDKClientCore {
             .map(|m| m.ticks_to_float_price(ticks))
     }
 
+    /// Given a side, price in ticks, and number of base lots, returns the lots locked by posting that order
+    pub fn capital_required(
+        &self,
+        market_key: &Pubkey,
+        side: Side,
+        price_in_ticks: u64,
+        num_base_lots: u64,
+    ) -> Result<u64> {
+        self.markets
+            .get(market_key)
+            .ok_or_else(|| anyhow!("Market not found! Please load in the market first"))
+            .map(|m| m.capital_required(side, price_in_ticks, num_base_lots))
+    }
+
+    /// Given two prices in quote units per raw base unit (represented as floats), returns the number of ticks between them
+    pub fn ticks_between_ui_prices(&self, market_key: &Pubkey, low: f64, high: f64) -> Result<u64> {
+        self.markets
+            .get(market_key)
+            .ok_or_else(|| anyhow!("Market not found! Please load in the market first"))
+            .map(|m| m.ticks_between_ui_prices(low, high))
+    }
+
+    /// Given a market and a size in raw base units (represented as a float), returns the base lots rounded down and up
+    pub fn representable_base_lots(
+        &self,
+        market_key: &Pubkey,
+        raw_base_units: f64,
+    ) -> Result<(u64, u64)> {
+        self.markets
+            .get(market_key)
+            .ok_or_else(|| anyhow!("Market not found! Please load in the market first"))
+            .map(|m| m.representable_base_lots(raw_base_units))
+    }
+
     /// Given a market, returns the base lot size in raw base units (as a float)
     pub fn raw_base_units_per_base_lot(&self, market_key: &Pubkey) -> Result<f64> {
         self.markets
@@ -0,0 +1,19 @@
+//! Fixtures shared by tests. Enabled with the `test-util` feature.
+use crate::sdk_client_core::MarketMetadata;
+
+/// Market metadata for SOL/USDC on Phoenix: 0.001 SOL base lots, 1 atom quote lots, and a tick
+/// size of 0.001 USDC, so one tick on one base lot is exactly one quote lot.
+pub fn sol_usdc_metadata() -> MarketMetadata {
+    MarketMetadata {
+        base_decimals: 9,
+        quote_decimals: 6,
+        base_atoms_per_raw_base_unit: 1_000_000_000,
+        quote_atoms_per_quote_unit: 1_000_000,
+        quote_atoms_per_quote_lot: 1,
+        base_atoms_per_base_lot: 1_000_000,
+        tick_size_in_quote_atoms_per_base_unit: 1000,
+        num_base_lots_per_base_unit: 1000,
+        raw_base_units_per_base_unit: 1,
+        ..Default::default()
+    }
+}
@@ -304,6 +304,62 @@ fn test_ticks_to_float_price() {
     assert_eq!(float_price, 10.907);
 }
 
+#[test]
+fn test_capital_required() {
+    let market = Pubkey::new_unique();
+    let core = setup(&market);
+    // 1 base unit (100 base lots) at 10.907 is 10907000 quote atoms, or 1090700 quote lots
+    let quote_lots = core
+        .capital_required(&market, Side::Bid, 10907, 100)
+        .unwrap();
+    assert_eq!(quote_lots, 1090700);
+    // One base lot at one tick is exactly one quote lot on this market
+    let quote_lots = core.capital_required(&market, Side::Bid, 1, 1).unwrap();
+    assert_eq!(quote_lots, 1);
+    let base_lots = core
+        .capital_required(&market, Side::Ask, 10907, 100)
+        .unwrap();
+    assert_eq!(base_lots, 100);
+}
+
+#[test]
+fn test_ticks_between_ui_prices() {
+    let market = Pubkey::new_unique();
+    let core = setup(&market);
+    // 0.01 is 10 ticks of 0.001, even though neither price is exact as a float
+    let ticks = core.ticks_between_ui_prices(&market, 10.9, 10.91).unwrap();
+    assert_eq!(ticks, 10);
+    let ticks = core
+        .ticks_between_ui_prices(&market, 0.1 + 0.2, 0.3)
+        .unwrap();
+    assert_eq!(ticks, 0);
+    let ticks = core.ticks_between_ui_prices(&market, 10.91, 10.9).unwrap();
+    assert_eq!(ticks, 0);
+
+    // Raw base unit multiplier will not affect the result if the tick size and base lots per base unit are adjusted accordingly.
+    let core = setup_with_raw_base_unit_multiplier(&market, 100);
+    let ticks = core.ticks_between_ui_prices(&market, 10.9, 10.91).unwrap();
+    assert_eq!(ticks, 10);
+}
+
+#[test]
+fn test_representable_base_lots() {
+    let market = Pubkey::new_unique();
+    let core = setup(&market);
+    // Base lots are 0.01 SOL
+    assert_eq!(
+        core.representable_base_lots(&market, 1.234).unwrap(),
+        (123, 124)
+    );
+    assert_eq!(
+        core.representable_base_lots(&market, 1.23).unwrap(),
+        (123, 123)
+    );
+    assert!(core
+        .representable_base_lots(&Pubkey::new_unique(), 1.0)
+        .is_err());
+}
+
 #[test]
 fn test_fill_event_to_quote_atoms() {
     let market = Pubkey::new_unique();
@@ -1,4 +1,21 @@
-use phoenix::state::{markets::Ladder, Side};
+//! Simulations and metrics over Phoenix `Ladder`s.
+//!
+//! Overflow contract: every method here that accumulates sizes or notionals across levels
+//! either adds them with `saturating_add` (in `u128` for notionals) or adds base lots in `u64`
+//! only while they are bounded by the order size, and returns totals as `u128` or converts them
+//! back to `u64` saturating at `u64::MAX`. None of them panic on overflow for a ladder that
+//! passes `LadderMetrics::is_well_formed`, however deep it is. Conversions to UI units through
+//! `MarketMetadata` are outside this contract, since they scale lots by the market's atom sizes.
+use phoenix::quantities::{BaseLots, QuoteLots, Ticks, WrapperU64};
+use phoenix::state::{
+    markets::{FIFOOrderId, FIFORestingOrder, Ladder, LadderOrder, Market},
+    OrderPacket, SelfTradeBehavior, Side,
+};
+use phoenix_sdk_core::orderbook::Orderbook;
+use phoenix_sdk_core::sdk_client_core::{MarketMetadata, PhoenixOrder};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
 
 #[derive(Debug, Clone)]
 pub struct SimulationSummaryInLots {
@@ -6,167 +23,4149 @@ pub struct SimulationSummaryInLots {
     pub quote_lots_filled: u64,
 }
 
+/// Same as `SimulationSummaryInLots`, but with the quote total widened to `u128` for books deep
+/// enough that the notional overflows a `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationSummaryInLotsU128 {
+    pub base_lots_filled: u64,
+    pub quote_lots_filled: u128,
+}
+
+/// Signed change to a trader's inventory, in lots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PositionDelta {
+    pub base_delta: i64,
+    pub quote_delta: i64,
+}
+
+impl SimulationSummaryInLots {
+    pub fn base_lots_filled_typed(&self) -> BaseLots {
+        BaseLots::new(self.base_lots_filled)
+    }
+
+    pub fn quote_lots_filled_typed(&self) -> QuoteLots {
+        QuoteLots::new(self.quote_lots_filled)
+    }
+
+    /// Expresses the fill as a change in inventory for a taker on `side`: a buy (`Side::Bid`)
+    /// adds base lots and spends quote lots, and a sell (`Side::Ask`) does the reverse.
+    /// Amounts that don't fit in an `i64` saturate.
+    pub fn as_position_delta(&self, side: Side) -> PositionDelta {
+        let base_lots = i64::try_from(self.base_lots_filled).unwrap_or(i64::MAX);
+        let quote_lots = i64::try_from(self.quote_lots_filled).unwrap_or(i64::MAX);
+        match side {
+            Side::Bid => PositionDelta {
+                base_delta: base_lots,
+                quote_delta: -quote_lots,
+            },
+            Side::Ask => PositionDelta {
+                base_delta: -base_lots,
+                quote_delta: quote_lots,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FillCounts {
+    /// Number of levels that were taken in full.
+    pub fully_consumed_levels: usize,
+    /// Number of levels that were only partially taken (typically the last level of a sweep).
+    pub partially_consumed_levels: usize,
+}
+
+/// A market order fill with the taker fee applied to its quote leg.
+#[derive(Debug, Clone)]
+pub struct FeeAdjustedSummary {
+    /// Lots filled before fees.
+    pub summary: SimulationSummaryInLots,
+    /// Quote lots traded before fees.
+    pub gross_quote_lots: u64,
+    /// Taker fee in quote lots, rounded up to a whole lot.
+    pub fee_quote_lots: u64,
+    /// Quote lots paid including the fee for a buy, or received after the fee for a sell.
+    pub net_quote_lots: u64,
+}
+
+/// UI-denominated preview of a limit order that may partially cross the spread, suitable for a
+/// confirmation screen.
+#[derive(Debug, Clone, Copy)]
+pub struct LimitOrderPreview {
+    /// Base units filled immediately against the opposite side of the book.
+    pub immediate_fill_base_ui: f64,
+    /// Quote units spent on a buy, or received on a sell, by the immediate fill.
+    pub immediate_fill_quote_ui: f64,
+    /// Average price of the immediate fill in quote units per base unit, or 0 if nothing filled.
+    pub avg_fill_price: f64,
+    /// Base units left over to rest on the book.
+    pub resting_base_ui: f64,
+    /// Price the remainder rests at in quote units per base unit, or None if nothing rests.
+    pub resting_price: Option<f64>,
+}
+
+/// UI-denominated preview of a market order, suitable for a confirmation screen.
+#[derive(Debug, Clone, Copy)]
+pub struct ExecutionPreview {
+    /// Base units bought or sold.
+    pub base_ui_filled: f64,
+    /// Quote units spent on a buy, or received on a sell.
+    pub quote_ui_spent: f64,
+    /// Average fill price in quote units per base unit, or 0 if nothing filled.
+    pub avg_price: f64,
+    /// Distance of the average fill price from the best price, in basis points.
+    pub price_impact_bps: f64,
+}
+
+/// Outcome of submitting a limit order that may cross the book.
+#[derive(Debug, Clone)]
+pub struct MarketableLimitResult {
+    /// Liquidity taken immediately, up to the limit price.
+    pub taker_summary: SimulationSummaryInLots,
+    /// Quote lots traded as taker, i.e. the notional that pays taker fees immediately.
+    pub taker_quote_lots: u64,
+    /// Base lots left over that would rest on the book.
+    pub resting_base_lots: u64,
+    /// Price the remainder would rest at, which is the packet's limit price.
+    pub resting_price_in_ticks: u64,
+}
+
+/// Which limit stopped a simulation with both a base and a quote cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingCap {
+    Base,
+    Quote,
+}
+
+#[derive(Debug, Clone)]
+pub struct DualCappedResult {
+    pub summary: SimulationSummaryInLots,
+    /// The cap that stopped the order, or None if the book ran out first.
+    /// If both caps bind at the same level, the base cap is reported.
+    pub binding_cap: Option<BindingCap>,
+}
+
+/// Outcome of an immediate-or-cancel order with a minimum fill size.
+#[derive(Debug, Clone)]
+pub enum IocOutcome {
+    /// The order filled at least its minimum size.
+    Filled(SimulationSummaryInLots),
+    /// The order would have filled less than its minimum size, so the whole order is voided.
+    Voided {
+        /// Base lots that were available within the order's budget and price cap.
+        base_lots_available: u64,
+    },
+}
+
+/// Changes to one side of the book between two ladders, matched by price. Sizes at repeated
+/// prices are summed before comparing. Each list is in book order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LadderSideDiff {
+    /// Levels at prices that only appear in the newer ladder.
+    pub added: Vec<LadderOrder>,
+    /// Levels at prices that only appear in the older ladder.
+    pub removed: Vec<LadderOrder>,
+    /// `(price_in_ticks, old_size_in_base_lots, new_size_in_base_lots)` for prices whose size
+    /// changed.
+    pub resized: Vec<(u64, u64, u64)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LadderDiff {
+    pub bids: LadderSideDiff,
+    pub asks: LadderSideDiff,
+}
+
+/// A broken ladder invariant reported by `LadderMetrics::is_well_formed`. `index` is the
+/// position of the offending level on `side`, counted from the best price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LadderError {
+    ZeroPrice {
+        side: Side,
+        index: usize,
+    },
+    ZeroSize {
+        side: Side,
+        index: usize,
+    },
+    /// The level is priced better than the level before it.
+    OutOfOrder {
+        side: Side,
+        index: usize,
+        price_in_ticks: u64,
+        previous_price_in_ticks: u64,
+    },
+}
+
+impl std::fmt::Display for LadderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LadderError::ZeroPrice { side, index } => {
+                write!(f, "{:?} level {} has a zero price", side, index)
+            }
+            LadderError::ZeroSize { side, index } => {
+                write!(f, "{:?} level {} has a zero size", side, index)
+            }
+            LadderError::OutOfOrder {
+                side,
+                index,
+                price_in_ticks,
+                previous_price_in_ticks,
+            } => write!(
+                f,
+                "{:?} level {} at {} ticks is out of order after {} ticks",
+                side, index, price_in_ticks, previous_price_in_ticks
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LadderError {}
+
+/// A level of the book touched by a simulated market order.
+#[derive(Debug, Clone, Copy)]
+struct LevelFill {
+    /// Index of the level within its side of the ladder.
+    level_index: usize,
+    price_in_ticks: u64,
+    base_lots_filled: u64,
+    level_size_in_base_lots: u64,
+}
+
+/// Walks the side of the book opposite to `side`, returning every level that the market order
+/// would take from. `size_in_lots` is in quote lots for `Side::Bid` and base lots for `Side::Ask`,
+/// matching `MarketSimulator::simulate_market_sell`.
+fn walk_levels(ladder: &Ladder, side: Side, size_in_lots: u64) -> Vec<LevelFill> {
+    let mut remaining_lots = size_in_lots;
+    let mut fills = vec![];
+    let levels = match side {
+        Side::Bid => &ladder.asks,
+        Side::Ask => &ladder.bids,
+    };
+    for (level_index, level) in levels.iter().enumerate() {
+        if remaining_lots == 0 {
+            break;
+        }
+        let base_lots_filled = match side {
+            Side::Bid => (remaining_lots / level.price_in_ticks).min(level.size_in_base_lots),
+            Side::Ask => remaining_lots.min(level.size_in_base_lots),
+        };
+        if base_lots_filled == 0 {
+            continue;
+        }
+        remaining_lots -= match side {
+            Side::Bid => base_lots_filled * level.price_in_ticks,
+            Side::Ask => base_lots_filled,
+        };
+        fills.push(LevelFill {
+            level_index,
+            price_in_ticks: level.price_in_ticks,
+            base_lots_filled,
+            level_size_in_base_lots: level.size_in_base_lots,
+        });
+    }
+    fills
+}
+
+/// Returns true if `price_in_ticks` is strictly better than `other_price_in_ticks` for a resting
+/// order on `side`: higher for bids and lower for asks.
+fn is_better_price(side: Side, price_in_ticks: u64, other_price_in_ticks: u64) -> bool {
+    match side {
+        Side::Bid => price_in_ticks > other_price_in_ticks,
+        Side::Ask => price_in_ticks < other_price_in_ticks,
+    }
+}
+
+fn diff_levels(side: Side, old: &[LadderOrder], new: &[LadderOrder]) -> LadderSideDiff {
+    let aggregate = |levels: &[LadderOrder]| {
+        let mut sizes = BTreeMap::new();
+        for level in levels.iter() {
+            let size = sizes.entry(level.price_in_ticks).or_insert(0_u64);
+            *size = size.saturating_add(level.size_in_base_lots);
+        }
+        sizes
+    };
+    let old_sizes = aggregate(old);
+    let new_sizes = aggregate(new);
+    let mut prices = old_sizes
+        .keys()
+        .chain(new_sizes.keys())
+        .copied()
+        .collect::<Vec<_>>();
+    prices.sort_unstable();
+    prices.dedup();
+    if side == Side::Bid {
+        prices.reverse();
+    }
+
+    let mut diff = LadderSideDiff::default();
+    for price_in_ticks in prices {
+        match (
+            old_sizes.get(&price_in_ticks),
+            new_sizes.get(&price_in_ticks),
+        ) {
+            (None, Some(&size_in_base_lots)) => diff.added.push(LadderOrder {
+                price_in_ticks,
+                size_in_base_lots,
+            }),
+            (Some(&size_in_base_lots), None) => diff.removed.push(LadderOrder {
+                price_in_ticks,
+                size_in_base_lots,
+            }),
+            (Some(&old_size), Some(&new_size)) if old_size != new_size => {
+                diff.resized.push((price_in_ticks, old_size, new_size))
+            }
+            _ => {}
+        }
+    }
+    diff
+}
+
+/// Returns the taker fee on `quote_lots`, rounded up to a whole quote lot as the matching engine
+/// does.
+fn taker_fee_in_quote_lots(quote_lots: u64, taker_fee_bps: u16) -> u64 {
+    let fee = (quote_lots as u128 * taker_fee_bps as u128).div_ceil(10_000);
+    u64::try_from(fee).unwrap_or(u64::MAX)
+}
+
+/// Returns the average price in ticks of a market order on `side` once the taker fee, rounded as
+/// in `taker_fee_in_quote_lots`, is added to the quote lots of a buy or taken off those of a sell.
+/// Returns None if nothing filled.
+fn all_in_avg_price_in_ticks(
+    side: Side,
+    summary: &SimulationSummaryInLots,
+    taker_fee_bps: u16,
+) -> Option<f64> {
+    if summary.base_lots_filled == 0 {
+        return None;
+    }
+    let fee_quote_lots = taker_fee_in_quote_lots(summary.quote_lots_filled, taker_fee_bps);
+    let quote_lots = match side {
+        Side::Bid => summary.quote_lots_filled.saturating_add(fee_quote_lots),
+        Side::Ask => summary.quote_lots_filled.saturating_sub(fee_quote_lots),
+    };
+    Some(quote_lots as f64 / summary.base_lots_filled as f64)
+}
+
+fn summarize_fills(fills: &[LevelFill]) -> SimulationSummaryInLots {
+    let quote_lots_filled = fills
+        .iter()
+        .map(|fill| fill.base_lots_filled as u128 * fill.price_in_ticks as u128)
+        .fold(0_u128, |total, quote_lots| total.saturating_add(quote_lots));
+    SimulationSummaryInLots {
+        base_lots_filled: fills.iter().map(|fill| fill.base_lots_filled).sum(),
+        quote_lots_filled: u64::try_from(quote_lots_filled).unwrap_or(u64::MAX),
+    }
+}
+
+/// Simulates spending `num_lots_quote` quote lots on `asks`, which must be sorted from the best
+/// (lowest) price outwards. This is the sweep behind `MarketSimulator::sell_quote`.
+pub fn simulate_buy_against_asks(
+    asks: &[LadderOrder],
+    num_lots_quote: u64,
+) -> SimulationSummaryInLots {
+    let mut remaining_quote_lots = num_lots_quote;
+    let mut base_lots = 0;
+
+    for ask in asks.iter() {
+        if remaining_quote_lots == 0 {
+            break;
+        }
+
+        let max_base_lots_you_can_buy = remaining_quote_lots / ask.price_in_ticks;
+        let amount_lots_to_buy = max_base_lots_you_can_buy.min(ask.size_in_base_lots);
+        base_lots += amount_lots_to_buy;
+        remaining_quote_lots -= amount_lots_to_buy * ask.price_in_ticks;
+    }
+
+    let quote_lots_used = num_lots_quote - remaining_quote_lots;
+    SimulationSummaryInLots {
+        base_lots_filled: base_lots,
+        quote_lots_filled: quote_lots_used,
+    }
+}
+
+/// Simulates selling `num_lots_base` base lots into `bids`, which must be sorted from the best
+/// (highest) price outwards. This is the sweep behind `MarketSimulator::sell_base`.
+pub fn simulate_sell_against_bids(
+    bids: &[LadderOrder],
+    num_lots_base: u64,
+) -> SimulationSummaryInLots {
+    let summary = simulate_sell_against_bids_u128(bids, num_lots_base);
+    SimulationSummaryInLots {
+        base_lots_filled: summary.base_lots_filled,
+        quote_lots_filled: u64::try_from(summary.quote_lots_filled).unwrap_or(u64::MAX),
+    }
+}
+
+fn simulate_sell_against_bids_u128(
+    bids: &[LadderOrder],
+    num_lots_base: u64,
+) -> SimulationSummaryInLotsU128 {
+    let mut remaining_base_lots = num_lots_base;
+    let mut quote_lots = 0_u128;
+
+    for bid in bids.iter() {
+        if remaining_base_lots == 0 {
+            break;
+        }
+
+        let lots_to_fill = remaining_base_lots.min(bid.size_in_base_lots);
+        quote_lots = quote_lots.saturating_add(lots_to_fill as u128 * bid.price_in_ticks as u128);
+        remaining_base_lots -= lots_to_fill;
+    }
+
+    SimulationSummaryInLotsU128 {
+        base_lots_filled: num_lots_base - remaining_base_lots,
+        quote_lots_filled: quote_lots,
+    }
+}
+
+/// Simulates a market order on `side` against bare `(price_in_ticks, size_in_base_lots)` levels
+/// from the opposite side of the book, sorted from the best price outwards. `size_in_lots` is in
+/// quote lots for `Side::Bid` and base lots for `Side::Ask`, as in
+/// `MarketSimulator::simulate_market_sell`.
+pub fn simulate_from_levels(
+    side: Side,
+    levels: &[(u64, u64)],
+    size_in_lots: u64,
+) -> SimulationSummaryInLots {
+    let levels = levels
+        .iter()
+        .map(|&(price_in_ticks, size_in_base_lots)| LadderOrder {
+            price_in_ticks,
+            size_in_base_lots,
+        })
+        .collect::<Vec<_>>();
+    match side {
+        Side::Bid => simulate_buy_against_asks(&levels, size_in_lots),
+        Side::Ask => simulate_sell_against_bids(&levels, size_in_lots),
+    }
+}
+
+/// Simulations of taking liquidity from the book. A `side` argument is the taker's side:
+/// `Side::Bid` spends quote lots against the asks and `Side::Ask` sells base lots into the bids.
 pub trait MarketSimulator {
     fn sell_quote(&self, num_lots_quote: u64) -> SimulationSummaryInLots;
     fn sell_base(&self, num_lots_base: u64) -> SimulationSummaryInLots;
+    /// Same as `sell_base`, but accumulates the quote lots received in a `u128`.
+    fn sell_base_u128(&self, num_lots_base: u64) -> SimulationSummaryInLotsU128;
     fn simulate_market_sell(&self, side: Side, size_in_lots: u64) -> SimulationSummaryInLots;
+
+    /// Simulates spending `quote_ui_amount` whole quote units (e.g. 500.0 USDC) on the asks.
+    /// The UI amount is converted to quote lots with `metadata`'s lot size and decimals, rounding
+    /// down to a whole lot, so the simulated spend never exceeds the requested amount.
+    fn simulate_buy_quote_ui(
+        &self,
+        quote_ui_amount: f64,
+        metadata: &MarketMetadata,
+    ) -> SimulationSummaryInLots;
+
+    /// Simulates a market order and also reports how many levels were taken in full versus
+    /// only partially.
+    fn simulate_with_fill_counts(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+    ) -> (SimulationSummaryInLots, FillCounts);
+
+    /// Previews spending `budget_ui` whole units of the input token: quote units for a buy
+    /// (`Side::Bid`) and base units for a sell (`Side::Ask`). The budget is rounded down to
+    /// whole lots before simulating.
+    fn quote_for_budget_ui(
+        &self,
+        side: Side,
+        budget_ui: f64,
+        metadata: &MarketMetadata,
+    ) -> ExecutionPreview;
+
+    /// Returns true if a taker order on `side` for `size_in_base_lots` could fill completely at
+    /// or better than `limit_price_in_ticks`. A `None` limit accepts any price.
+    /// This only sums resting depth and does not build a simulation summary.
+    ///
+    /// Unlike the other methods of this trait, the size is in base lots for both sides, since a
+    /// fill-or-kill order is sized in base lots: a `Side::Bid` checks whether the asks within the
+    /// limit hold `size_in_base_lots`, not whether that many quote lots could be spent.
+    fn can_fill_fully(
+        &self,
+        side: Side,
+        size_in_base_lots: u64,
+        limit_price_in_ticks: Option<u64>,
+    ) -> bool;
+
+    /// Simulates submitting `packet`, sweeping the opposite side of the book up to the packet's
+    /// limit price and reporting what would be left to rest. Only the base lot size of the packet
+    /// is considered. Post-only packets never take liquidity, and IOC packets never rest.
+    ///
+    /// An IOC packet with `price_in_ticks: None` is a market order and takes at any price, while
+    /// `Some(Ticks::ZERO)` is a literal limit of zero ticks: a buy never fills and a sell takes
+    /// any bid.
+    fn simulate_marketable_limit(&self, packet: &OrderPacket) -> MarketableLimitResult;
+
+    /// Converts `simulate_marketable_limit` for `packet` into UI units with the market's
+    /// metadata, splitting the order into what fills immediately and what would rest.
+    fn preview_limit_order(
+        &self,
+        packet: &OrderPacket,
+        metadata: &MarketMetadata,
+    ) -> LimitOrderPreview;
+
+    /// Simulates taking every level on the opposite side of the book: buying the entire ask side
+    /// for `Side::Bid`, or selling into the entire bid side for `Side::Ask`. Totals are accumulated
+    /// in `u128` and saturate at `u64::MAX` when converted back.
+    fn sweep_entire_side(&self, side: Side) -> SimulationSummaryInLots;
+
+    /// Returns the largest market order on `side`, in base lots, whose average fill price stays
+    /// within `worst_avg_price_in_ticks`: at or below it for a buy (`Side::Bid`), at or above it
+    /// for a sell (`Side::Ask`). The last level taken may be partially filled.
+    fn max_size_within_avg_price(&self, side: Side, worst_avg_price_in_ticks: u64) -> u64;
+
+    /// Simulates a limit order pegged to the best price on the opposite side of the book: a buy
+    /// is priced at the best ask minus `offset_in_ticks`, and a sell at the best bid plus
+    /// `offset_in_ticks`. The pegged price is clamped to at least one tick. If the opposite side
+    /// is empty there is nothing to peg to, and nothing fills or rests.
+    fn simulate_pegged(
+        &self,
+        side: Side,
+        offset_in_ticks: i64,
+        size_in_base_lots: u64,
+    ) -> MarketableLimitResult;
+
+    /// Simulates an IOC buy spending up to `quote_lots` on asks priced at or below
+    /// `max_price_in_ticks`. The order is voided if it would fill fewer than
+    /// `min_base_lots_to_fill` base lots, mirroring a buy-side `OrderPacket::ImmediateOrCancel`.
+    ///
+    /// `None` is a market order that takes asks at any price. `Some(0)` is a cap of zero ticks,
+    /// which no ask can meet, so it never fills; it is not a market order.
+    fn simulate_ioc_buy(
+        &self,
+        quote_lots: u64,
+        max_price_in_ticks: Option<u64>,
+        min_base_lots_to_fill: u64,
+    ) -> IocOutcome;
+
+    /// Simulates a market order on `side` that stops as soon as either `max_base_lots` base lots
+    /// have filled or the next lot would take the quote lots traded past `max_quote_lots`.
+    fn simulate_dual_capped(
+        &self,
+        side: Side,
+        max_base_lots: u64,
+        max_quote_lots: u64,
+    ) -> DualCappedResult;
+
+    /// Returns the average fill price in ticks for trading a fixed notional of `quote_lots`:
+    /// spending it on the asks for `Side::Bid`, or selling into the bids until it is received
+    /// for `Side::Ask`. Returns None if the book cannot absorb the full notional.
+    fn price_for_notional(&self, side: Side, quote_lots: u64) -> Option<f64>;
+
+    /// Simulates a market order like `simulate_market_sell` that stops as soon as
+    /// `target_ratio` of `size_in_lots` has filled, in the same units as `size_in_lots`.
+    /// The ratio is clamped to `[0, 1]` and the target is rounded up to a whole lot.
+    fn simulate_until_ratio(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        target_ratio: f64,
+    ) -> SimulationSummaryInLots;
+
+    /// Returns the all-in average price in ticks of a market order on `side` once the taker fee is
+    /// included: the fee raises the effective price of a buy (`Side::Bid`) and lowers the
+    /// effective price of a sell (`Side::Ask`). The fee is rounded up to a whole quote lot as the
+    /// matching engine does. Returns None if nothing would fill.
+    fn taker_break_even_price_in_ticks(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        taker_fee_bps: u16,
+    ) -> Option<f64>;
+
+    /// Simulates a market order like `simulate_market_sell` without merging levels that share a
+    /// price, returning `(level_index, base_lots_taken)` for each level taken from, where
+    /// `level_index` is the level's position on its side of the ladder. Repeated levels at one
+    /// price are resting orders in FIFO order, so this preserves their queue positions.
+    fn simulate_preserving_queue(&self, side: Side, size_in_lots: u64) -> Vec<(usize, u64)>;
+
+    /// Simulates a market order like `simulate_market_sell` and returns the
+    /// `(price_in_ticks, base_lots)` trade print generated at each level, in fill order.
+    fn simulated_prints(&self, side: Side, size_in_lots: u64) -> Vec<(u64, u64)>;
+
+    /// Returns the worst price in ticks a market order like `simulate_market_sell` would execute
+    /// at: the highest ask taken by a buy (`Side::Bid`), or the lowest bid taken by a sell
+    /// (`Side::Ask`). Returns None if nothing would fill.
+    fn worst_price_in_ticks(&self, side: Side, size_in_lots: u64) -> Option<u64>;
+
+    /// Simulates a market order like `simulate_market_sell` and returns
+    /// `(bucket_floor_price_in_ticks, base_lots_filled_in_bucket)` for each fixed-width price
+    /// bucket the order filled in, in the order they were reached.
+    /// A `bucket_size_in_ticks` of 0 is treated as 1.
+    fn fill_histogram(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        bucket_size_in_ticks: u64,
+    ) -> Vec<(u64, u64)>;
+
+    /// Simulates buying `base_lots` base lots from the asks for the first leg of a delta-neutral
+    /// pair trade, returning the fill and the quote lots paid for it. The quote lots are the exact
+    /// notional the hedge leg needs to offset, since fills are priced in whole quote lots.
+    fn simulate_delta_neutral(&self, base_lots: u64) -> (SimulationSummaryInLots, u64);
+
+    /// Simulates a market order like `simulate_market_sell` and returns, for each level taken from,
+    /// `(cumulative_base_lots_filled, ticks_from_mid)`: the base lots filled so far and how far
+    /// that level's price is from the mid price before the trade. Distances are rounded up to a
+    /// whole tick, so the touch across a one tick spread is 1 tick from the mid. Returns an empty
+    /// vector if either side of the book is empty, since there is no mid price.
+    fn spread_crossed_per_lot(&self, side: Side, size_in_lots: u64) -> Vec<(u64, u64)>;
+
+    /// Returns the smallest order size, in base lots, at which moving up to a cheaper taker fee
+    /// tier pays for the extra slippage of trading more. `fee_tiers` holds
+    /// `(min_base_lots, taker_fee_bps)` for each tier. The baseline is an order of the lowest
+    /// tier's minimum size (at least one lot) at that tier's fee, and each cheaper tier's minimum
+    /// size is checked in increasing order until its all-in average price, fees included, is at
+    /// least as good as the baseline's. All-in prices are computed as in
+    /// `taker_break_even_price_in_ticks`. Returns None if no tier breaks even or the book cannot
+    /// fill the sizes involved.
+    fn fee_tier_break_even(&self, side: Side, fee_tiers: &[(u64, u16)]) -> Option<u64>;
+
+    /// Simulates spending `quote_lots` on the asks like `sell_quote`, with the taker fee charged
+    /// on top of the quote lots spent, so the buyer pays more than the gross notional.
+    fn simulate_buy_with_fees(&self, quote_lots: u64, taker_fee_bps: u16) -> FeeAdjustedSummary;
+
+    /// Simulates selling `size_in_base_lots` into the bids like `sell_base`, with the taker fee
+    /// deducted from the quote lots received, so the seller nets less than the gross notional.
+    fn simulate_sell_with_fees(
+        &self,
+        size_in_base_lots: u64,
+        taker_fee_bps: u16,
+    ) -> FeeAdjustedSummary;
+
+    /// Simulates a market order like `simulate_market_sell` that is voided if the quote lots it
+    /// would trade fall below `min_notional_quote_lots`. A fill of exactly the minimum notional
+    /// is allowed.
+    fn simulate_with_min_notional(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        min_notional_quote_lots: u64,
+    ) -> IocOutcome;
+
+    /// Simulates a market order like `simulate_market_sell` against a book where some levels are
+    /// known to refill after being taken, e.g. icebergs. `replenish_levels` maps a price in ticks
+    /// to the number of times every level at that price refills to its displayed size, so such a
+    /// level can supply up to `(1 + refills) * size_in_base_lots`. Prices not in the map do not
+    /// refill.
+    fn simulate_with_replenishment(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        replenish_levels: &HashMap<u64, u8>,
+    ) -> SimulationSummaryInLots;
+
+    /// Simulates a market order like `simulate_market_sell` against a copy of the book with
+    /// `my_order` added, such as one from `OrderPacketExt::as_resting_order`. The order's side is
+    /// read from its order id, and it is placed at `LadderMetrics::fifo_insertion_index`, behind
+    /// every existing level at its price. Returns the taker's fill and how many base lots of
+    /// `my_order` it took.
+    fn simulate_with_my_order(
+        &self,
+        my_order: (FIFOOrderId, PhoenixOrder),
+        side: Side,
+        size_in_lots: u64,
+    ) -> (SimulationSummaryInLots, u64);
+
+    /// Simulates a market order like `simulate_market_sell` at each of `sizes`, returning the
+    /// summaries in the same order as `sizes`. The sizes are sorted internally, so the book is
+    /// walked once for all of them instead of once per size. Assumes the book is sorted from the
+    /// best price outwards, as ladders from the program are.
+    fn simulate_checkpoints(&self, side: Side, sizes: &[u64]) -> Vec<SimulationSummaryInLots>;
+
+    /// Advisory estimate of the average price in ticks of a market order like
+    /// `simulate_market_sell` that is larger than the visible book. The visible part fills at the
+    /// book's prices, and any residual left after the whole side is taken, in base lots, is
+    /// assumed to fill at the worst visible price moved against the taker by
+    /// `impact_coefficient * sqrt(residual_base_lots)` ticks. A residual quote budget on a buy is
+    /// converted to base lots at the worst visible price. Orders the book can absorb get the
+    /// plain sweep average. Returns None if nothing would fill or the coefficient is negative or
+    /// not finite.
+    fn impact_adjusted_price_in_ticks(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        impact_coefficient: f64,
+    ) -> Option<f64>;
+
+    /// Simulates a market order like `simulate_market_sell` and returns an IOC packet for it: a
+    /// base lot budget equal to the simulated fill, and a limit price `slippage_bps` beyond the
+    /// worst price the simulation reached. The buffer is rounded down to a whole tick so the limit
+    /// never allows more than `slippage_bps` of slippage. The packet uses the SDK's default
+    /// `SelfTradeBehavior::CancelProvide` and no match limit. Returns None if nothing would fill.
+    fn recommended_packet(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        slippage_bps: u16,
+        client_order_id: u128,
+    ) -> Option<OrderPacket>;
 }
 
-impl MarketSimulator for Ladder {
-    fn sell_quote(&self, num_lots_quote: u64) -> SimulationSummaryInLots {
-        let mut remaining_quote_lots = num_lots_quote;
-        let mut base_lots = 0;
+impl MarketSimulator for Ladder {
+    fn sell_quote(&self, num_lots_quote: u64) -> SimulationSummaryInLots {
+        simulate_buy_against_asks(&self.asks, num_lots_quote)
+    }
+
+    fn sell_base(&self, num_lots_base: u64) -> SimulationSummaryInLots {
+        simulate_sell_against_bids(&self.bids, num_lots_base)
+    }
+
+    fn sell_base_u128(&self, num_lots_base: u64) -> SimulationSummaryInLotsU128 {
+        simulate_sell_against_bids_u128(&self.bids, num_lots_base)
+    }
+
+    fn simulate_market_sell(&self, side: Side, size_in_lots: u64) -> SimulationSummaryInLots {
+        match side {
+            Side::Bid => self.sell_quote(size_in_lots),
+            Side::Ask => self.sell_base(size_in_lots),
+        }
+    }
+
+    fn simulate_buy_quote_ui(
+        &self,
+        quote_ui_amount: f64,
+        metadata: &MarketMetadata,
+    ) -> SimulationSummaryInLots {
+        let quote_atoms = quote_ui_amount.max(0.0) * metadata.quote_atoms_per_quote_unit as f64;
+        let quote_lots = (quote_atoms / metadata.quote_atoms_per_quote_lot as f64).floor() as u64;
+        self.sell_quote(quote_lots)
+    }
+
+    fn simulate_with_fill_counts(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+    ) -> (SimulationSummaryInLots, FillCounts) {
+        let fills = walk_levels(self, side, size_in_lots);
+        let mut fill_counts = FillCounts::default();
+        for fill in fills.iter() {
+            if fill.base_lots_filled == fill.level_size_in_base_lots {
+                fill_counts.fully_consumed_levels += 1;
+            } else {
+                fill_counts.partially_consumed_levels += 1;
+            }
+        }
+        (summarize_fills(&fills), fill_counts)
+    }
+
+    fn quote_for_budget_ui(
+        &self,
+        side: Side,
+        budget_ui: f64,
+        metadata: &MarketMetadata,
+    ) -> ExecutionPreview {
+        let budget_ui = budget_ui.max(0.0);
+        let lots_in = match side {
+            Side::Bid => metadata.quote_units_to_quote_lots(budget_ui),
+            Side::Ask => metadata.raw_base_units_to_base_lots_rounded_down(budget_ui),
+        };
+        let summary = self.simulate_market_sell(side, lots_in);
+        let base_ui_filled = metadata.base_atoms_to_raw_base_units_as_float(
+            metadata.base_lots_to_base_atoms(summary.base_lots_filled),
+        );
+        let quote_ui_spent = metadata.quote_atoms_to_quote_units_as_float(
+            metadata.quote_lots_to_quote_atoms(summary.quote_lots_filled),
+        );
+        if summary.base_lots_filled == 0 {
+            return ExecutionPreview {
+                base_ui_filled,
+                quote_ui_spent,
+                avg_price: 0.0,
+                price_impact_bps: 0.0,
+            };
+        }
+        let best_price_in_ticks = match side {
+            Side::Bid => self.asks[0].price_in_ticks,
+            Side::Ask => self.bids[0].price_in_ticks,
+        } as f64;
+        let avg_price_in_ticks = summary.quote_lots_filled as f64 / summary.base_lots_filled as f64;
+        ExecutionPreview {
+            base_ui_filled,
+            quote_ui_spent,
+            avg_price: quote_ui_spent / base_ui_filled,
+            price_impact_bps: (avg_price_in_ticks - best_price_in_ticks).abs()
+                / best_price_in_ticks
+                * 10_000.0,
+        }
+    }
+
+    fn can_fill_fully(
+        &self,
+        side: Side,
+        size_in_base_lots: u64,
+        limit_price_in_ticks: Option<u64>,
+    ) -> bool {
+        let levels = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+        let mut available_base_lots = 0_u64;
+        for level in levels.iter() {
+            if available_base_lots >= size_in_base_lots {
+                break;
+            }
+            if let Some(limit_price_in_ticks) = limit_price_in_ticks {
+                // Levels past the limit price cannot be taken
+                if is_better_price(side.opposite(), limit_price_in_ticks, level.price_in_ticks) {
+                    break;
+                }
+            }
+            available_base_lots = available_base_lots.saturating_add(level.size_in_base_lots);
+        }
+        available_base_lots >= size_in_base_lots
+    }
+
+    fn simulate_marketable_limit(&self, packet: &OrderPacket) -> MarketableLimitResult {
+        let side = packet.side();
+        let limit_price_in_ticks = packet.get_price_in_ticks().as_u64();
+        let num_base_lots = packet.num_base_lots().as_u64();
+        let mut fills = vec![];
+        if !matches!(packet, OrderPacket::PostOnly { .. }) {
+            let levels = match side {
+                Side::Bid => &self.asks,
+                Side::Ask => &self.bids,
+            };
+            let mut remaining_base_lots = num_base_lots;
+            for (level_index, level) in levels.iter().enumerate() {
+                if remaining_base_lots == 0
+                    || is_better_price(side.opposite(), limit_price_in_ticks, level.price_in_ticks)
+                {
+                    break;
+                }
+                let base_lots_filled = remaining_base_lots.min(level.size_in_base_lots);
+                remaining_base_lots -= base_lots_filled;
+                fills.push(LevelFill {
+                    level_index,
+                    price_in_ticks: level.price_in_ticks,
+                    base_lots_filled,
+                    level_size_in_base_lots: level.size_in_base_lots,
+                });
+            }
+        }
+        let taker_summary = summarize_fills(&fills);
+        let resting_base_lots = match packet {
+            OrderPacket::ImmediateOrCancel { .. } => 0,
+            _ => num_base_lots - taker_summary.base_lots_filled,
+        };
+        MarketableLimitResult {
+            taker_quote_lots: taker_summary.quote_lots_filled,
+            taker_summary,
+            resting_base_lots,
+            resting_price_in_ticks: limit_price_in_ticks,
+        }
+    }
+
+    fn preview_limit_order(
+        &self,
+        packet: &OrderPacket,
+        metadata: &MarketMetadata,
+    ) -> LimitOrderPreview {
+        let result = self.simulate_marketable_limit(packet);
+        let base_ui = |base_lots: u64| {
+            metadata
+                .base_atoms_to_raw_base_units_as_float(metadata.base_lots_to_base_atoms(base_lots))
+        };
+        let immediate_fill_base_ui = base_ui(result.taker_summary.base_lots_filled);
+        let immediate_fill_quote_ui = metadata.quote_atoms_to_quote_units_as_float(
+            metadata.quote_lots_to_quote_atoms(result.taker_summary.quote_lots_filled),
+        );
+        LimitOrderPreview {
+            immediate_fill_base_ui,
+            immediate_fill_quote_ui,
+            avg_fill_price: if result.taker_summary.base_lots_filled == 0 {
+                0.0
+            } else {
+                immediate_fill_quote_ui / immediate_fill_base_ui
+            },
+            resting_base_ui: base_ui(result.resting_base_lots),
+            resting_price: (result.resting_base_lots > 0)
+                .then(|| metadata.ticks_to_float_price(result.resting_price_in_ticks)),
+        }
+    }
+
+    fn sweep_entire_side(&self, side: Side) -> SimulationSummaryInLots {
+        let levels = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+        let (base_lots, quote_lots) =
+            levels
+                .iter()
+                .fold((0_u128, 0_u128), |(base_lots, quote_lots), level| {
+                    (
+                        base_lots.saturating_add(level.size_in_base_lots as u128),
+                        quote_lots.saturating_add(
+                            level.price_in_ticks as u128 * level.size_in_base_lots as u128,
+                        ),
+                    )
+                });
+        SimulationSummaryInLots {
+            base_lots_filled: u64::try_from(base_lots).unwrap_or(u64::MAX),
+            quote_lots_filled: u64::try_from(quote_lots).unwrap_or(u64::MAX),
+        }
+    }
+
+    fn max_size_within_avg_price(&self, side: Side, worst_avg_price_in_ticks: u64) -> u64 {
+        let levels = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+        let worst_price = worst_avg_price_in_ticks as u128;
+        let mut base_lots = 0_u128;
+        let mut quote_lots = 0_u128;
+        for level in levels.iter() {
+            let price = level.price_in_ticks as u128;
+            let size = level.size_in_base_lots as u128;
+            if !is_better_price(side, level.price_in_ticks, worst_avg_price_in_ticks) {
+                // Levels at or inside the threshold only improve the average
+                base_lots = base_lots.saturating_add(size);
+                quote_lots = quote_lots.saturating_add(price * size);
+                continue;
+            }
+            // Solve for the number of lots x at this level that keeps the running
+            // average at the threshold: (quote_lots + price * x) / (base_lots + x) = worst_price
+            let (slack, cost_per_lot) = match side {
+                Side::Bid => (
+                    worst_price
+                        .saturating_mul(base_lots)
+                        .saturating_sub(quote_lots),
+                    price - worst_price,
+                ),
+                Side::Ask => (
+                    quote_lots.saturating_sub(worst_price.saturating_mul(base_lots)),
+                    worst_price - price,
+                ),
+            };
+            let lots_within_threshold = slack / cost_per_lot;
+            if lots_within_threshold < size {
+                base_lots = base_lots.saturating_add(lots_within_threshold);
+                break;
+            }
+            // The whole level fits, and later levels may still fit within the slack it leaves
+            base_lots = base_lots.saturating_add(size);
+            quote_lots = quote_lots.saturating_add(price * size);
+        }
+        u64::try_from(base_lots).unwrap_or(u64::MAX)
+    }
+
+    fn simulate_pegged(
+        &self,
+        side: Side,
+        offset_in_ticks: i64,
+        size_in_base_lots: u64,
+    ) -> MarketableLimitResult {
+        let reference_level = match side {
+            Side::Bid => self.asks.first(),
+            Side::Ask => self.bids.first(),
+        };
+        let reference_price_in_ticks = match reference_level {
+            Some(level) => level.price_in_ticks as i128,
+            None => {
+                return MarketableLimitResult {
+                    taker_summary: SimulationSummaryInLots {
+                        base_lots_filled: 0,
+                        quote_lots_filled: 0,
+                    },
+                    taker_quote_lots: 0,
+                    resting_base_lots: 0,
+                    resting_price_in_ticks: 0,
+                }
+            }
+        };
+        let pegged_price_in_ticks = match side {
+            Side::Bid => reference_price_in_ticks - offset_in_ticks as i128,
+            Side::Ask => reference_price_in_ticks + offset_in_ticks as i128,
+        }
+        .clamp(1, u64::MAX as i128) as u64;
+        self.simulate_marketable_limit(&OrderPacket::new_limit_order_default(
+            side,
+            pegged_price_in_ticks,
+            size_in_base_lots,
+        ))
+    }
+
+    fn simulate_ioc_buy(
+        &self,
+        quote_lots: u64,
+        max_price_in_ticks: Option<u64>,
+        min_base_lots_to_fill: u64,
+    ) -> IocOutcome {
+        // No limit is the same as a limit no ask can be priced above
+        let max_price_in_ticks = max_price_in_ticks.unwrap_or(u64::MAX);
+        let eligible_asks = self
+            .asks
+            .iter()
+            .take_while(|ask| ask.price_in_ticks <= max_price_in_ticks)
+            .count();
+        let summary = simulate_buy_against_asks(&self.asks[..eligible_asks], quote_lots);
+        if summary.base_lots_filled < min_base_lots_to_fill {
+            return IocOutcome::Voided {
+                base_lots_available: summary.base_lots_filled,
+            };
+        }
+        IocOutcome::Filled(summary)
+    }
+
+    fn simulate_dual_capped(
+        &self,
+        side: Side,
+        max_base_lots: u64,
+        max_quote_lots: u64,
+    ) -> DualCappedResult {
+        let levels = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+        let mut remaining_base_lots = max_base_lots;
+        let mut remaining_quote_lots = max_quote_lots;
+        let mut binding_cap = None;
+        for level in levels.iter() {
+            if remaining_base_lots == 0 {
+                binding_cap = Some(BindingCap::Base);
+                break;
+            }
+            let base_lots = level
+                .size_in_base_lots
+                .min(remaining_base_lots)
+                .min(remaining_quote_lots / level.price_in_ticks);
+            remaining_base_lots -= base_lots;
+            remaining_quote_lots -= base_lots * level.price_in_ticks;
+            if base_lots < level.size_in_base_lots {
+                binding_cap = Some(if remaining_base_lots == 0 {
+                    BindingCap::Base
+                } else {
+                    BindingCap::Quote
+                });
+                break;
+            }
+        }
+        if binding_cap.is_none() && remaining_base_lots == 0 {
+            binding_cap = Some(BindingCap::Base);
+        }
+        DualCappedResult {
+            summary: SimulationSummaryInLots {
+                base_lots_filled: max_base_lots - remaining_base_lots,
+                quote_lots_filled: max_quote_lots - remaining_quote_lots,
+            },
+            binding_cap,
+        }
+    }
+
+    fn price_for_notional(&self, side: Side, quote_lots: u64) -> Option<f64> {
+        if quote_lots == 0 || self.sweep_entire_side(side).quote_lots_filled < quote_lots {
+            return None;
+        }
+        let summary = match side {
+            Side::Bid => self.sell_quote(quote_lots),
+            Side::Ask => {
+                // Find the base lots needed to receive the notional, rounding up at the last level
+                let mut remaining_quote_lots = quote_lots;
+                let mut base_lots = 0_u64;
+                for bid in self.bids.iter() {
+                    if remaining_quote_lots == 0 {
+                        break;
+                    }
+                    let lots_to_fill = (1 + (remaining_quote_lots - 1) / bid.price_in_ticks)
+                        .min(bid.size_in_base_lots);
+                    base_lots = base_lots.saturating_add(lots_to_fill);
+                    remaining_quote_lots = remaining_quote_lots
+                        .saturating_sub(lots_to_fill.saturating_mul(bid.price_in_ticks));
+                }
+                self.sell_base(base_lots)
+            }
+        };
+        if summary.base_lots_filled == 0 {
+            return None;
+        }
+        Some(summary.quote_lots_filled as f64 / summary.base_lots_filled as f64)
+    }
+
+    fn simulate_until_ratio(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        target_ratio: f64,
+    ) -> SimulationSummaryInLots {
+        let target_ratio = if target_ratio.is_nan() {
+            0.0
+        } else {
+            target_ratio.clamp(0.0, 1.0)
+        };
+        let target_lots = ((size_in_lots as f64 * target_ratio).ceil() as u64).min(size_in_lots);
+        self.simulate_market_sell(side, target_lots)
+    }
+
+    fn taker_break_even_price_in_ticks(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        taker_fee_bps: u16,
+    ) -> Option<f64> {
+        let summary = self.simulate_market_sell(side, size_in_lots);
+        all_in_avg_price_in_ticks(side, &summary, taker_fee_bps)
+    }
+
+    fn simulate_preserving_queue(&self, side: Side, size_in_lots: u64) -> Vec<(usize, u64)> {
+        walk_levels(self, side, size_in_lots)
+            .into_iter()
+            .map(|fill| (fill.level_index, fill.base_lots_filled))
+            .collect()
+    }
+
+    fn simulated_prints(&self, side: Side, size_in_lots: u64) -> Vec<(u64, u64)> {
+        walk_levels(self, side, size_in_lots)
+            .into_iter()
+            .map(|fill| (fill.price_in_ticks, fill.base_lots_filled))
+            .collect()
+    }
+
+    fn worst_price_in_ticks(&self, side: Side, size_in_lots: u64) -> Option<u64> {
+        walk_levels(self, side, size_in_lots)
+            .last()
+            .map(|fill| fill.price_in_ticks)
+    }
+
+    fn fill_histogram(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        bucket_size_in_ticks: u64,
+    ) -> Vec<(u64, u64)> {
+        let bucket_size_in_ticks = bucket_size_in_ticks.max(1);
+        let mut buckets: Vec<(u64, u64)> = vec![];
+        for fill in walk_levels(self, side, size_in_lots) {
+            let bucket_floor = fill.price_in_ticks / bucket_size_in_ticks * bucket_size_in_ticks;
+            match buckets.last_mut() {
+                Some((floor, size)) if *floor == bucket_floor => *size += fill.base_lots_filled,
+                _ => buckets.push((bucket_floor, fill.base_lots_filled)),
+            }
+        }
+        buckets
+    }
+
+    fn simulate_delta_neutral(&self, base_lots: u64) -> (SimulationSummaryInLots, u64) {
+        let summary = self
+            .simulate_dual_capped(Side::Bid, base_lots, u64::MAX)
+            .summary;
+        let hedge_quote_lots = summary.quote_lots_filled;
+        (summary, hedge_quote_lots)
+    }
+
+    fn spread_crossed_per_lot(&self, side: Side, size_in_lots: u64) -> Vec<(u64, u64)> {
+        let (best_bid, best_ask) = match (self.bids.first(), self.asks.first()) {
+            (Some(best_bid), Some(best_ask)) => (best_bid, best_ask),
+            _ => return vec![],
+        };
+        // Twice the mid price, so half-tick mids stay exact
+        let double_mid = best_bid.price_in_ticks as u128 + best_ask.price_in_ticks as u128;
+        let mut cumulative_base_lots = 0_u64;
+        walk_levels(self, side, size_in_lots)
+            .into_iter()
+            .map(|fill| {
+                cumulative_base_lots += fill.base_lots_filled;
+                let double_distance = (2 * fill.price_in_ticks as u128).abs_diff(double_mid);
+                let ticks_from_mid = u64::try_from(double_distance.div_ceil(2)).unwrap_or(u64::MAX);
+                (cumulative_base_lots, ticks_from_mid)
+            })
+            .collect()
+    }
+
+    fn fee_tier_break_even(&self, side: Side, fee_tiers: &[(u64, u16)]) -> Option<u64> {
+        let all_in_price_in_ticks = |base_lots: u64, taker_fee_bps: u16| {
+            let summary = self.simulate_dual_capped(side, base_lots, u64::MAX).summary;
+            if summary.base_lots_filled < base_lots {
+                return None;
+            }
+            all_in_avg_price_in_ticks(side, &summary, taker_fee_bps)
+        };
+        let mut fee_tiers = fee_tiers.to_vec();
+        fee_tiers.sort_by_key(|&(min_base_lots, _)| min_base_lots);
+        let (&(base_min_base_lots, base_fee_bps), cheaper_tiers) = fee_tiers.split_first()?;
+        let baseline = all_in_price_in_ticks(base_min_base_lots.max(1), base_fee_bps)?;
+        cheaper_tiers
+            .iter()
+            .filter(|&&(_, taker_fee_bps)| taker_fee_bps < base_fee_bps)
+            .find(|&&(min_base_lots, taker_fee_bps)| {
+                all_in_price_in_ticks(min_base_lots, taker_fee_bps).is_some_and(
+                    |price| match side {
+                        Side::Bid => price <= baseline,
+                        Side::Ask => price >= baseline,
+                    },
+                )
+            })
+            .map(|&(min_base_lots, _)| min_base_lots)
+    }
+
+    fn simulate_buy_with_fees(&self, quote_lots: u64, taker_fee_bps: u16) -> FeeAdjustedSummary {
+        let summary = self.sell_quote(quote_lots);
+        let fee_quote_lots = taker_fee_in_quote_lots(summary.quote_lots_filled, taker_fee_bps);
+        FeeAdjustedSummary {
+            gross_quote_lots: summary.quote_lots_filled,
+            fee_quote_lots,
+            net_quote_lots: summary.quote_lots_filled.saturating_add(fee_quote_lots),
+            summary,
+        }
+    }
+
+    fn simulate_sell_with_fees(
+        &self,
+        size_in_base_lots: u64,
+        taker_fee_bps: u16,
+    ) -> FeeAdjustedSummary {
+        let summary = self.sell_base(size_in_base_lots);
+        let fee_quote_lots = taker_fee_in_quote_lots(summary.quote_lots_filled, taker_fee_bps);
+        FeeAdjustedSummary {
+            gross_quote_lots: summary.quote_lots_filled,
+            fee_quote_lots,
+            net_quote_lots: summary.quote_lots_filled.saturating_sub(fee_quote_lots),
+            summary,
+        }
+    }
+
+    fn simulate_with_min_notional(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        min_notional_quote_lots: u64,
+    ) -> IocOutcome {
+        let summary = self.simulate_market_sell(side, size_in_lots);
+        if summary.quote_lots_filled < min_notional_quote_lots {
+            return IocOutcome::Voided {
+                base_lots_available: summary.base_lots_filled,
+            };
+        }
+        IocOutcome::Filled(summary)
+    }
+
+    fn simulate_with_replenishment(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        replenish_levels: &HashMap<u64, u8>,
+    ) -> SimulationSummaryInLots {
+        let levels = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+        // A refilled level is taken again at the same price before the sweep moves on, so it
+        // behaves like a single level with the refills added to its size
+        let levels = levels
+            .iter()
+            .map(|level| {
+                let refills = replenish_levels
+                    .get(&level.price_in_ticks)
+                    .copied()
+                    .unwrap_or(0);
+                LadderOrder {
+                    price_in_ticks: level.price_in_ticks,
+                    size_in_base_lots: level.size_in_base_lots.saturating_mul(1 + refills as u64),
+                }
+            })
+            .collect::<Vec<_>>();
+        match side {
+            Side::Bid => simulate_buy_against_asks(&levels, size_in_lots),
+            Side::Ask => simulate_sell_against_bids(&levels, size_in_lots),
+        }
+    }
+
+    fn simulate_with_my_order(
+        &self,
+        my_order: (FIFOOrderId, PhoenixOrder),
+        side: Side,
+        size_in_lots: u64,
+    ) -> (SimulationSummaryInLots, u64) {
+        let (order_id, order) = my_order;
+        let my_side = Side::from_order_sequence_number(order_id.order_sequence_number);
+        let price_in_ticks = order_id.price_in_ticks.as_u64();
+        let my_index = self.fifo_insertion_index(my_side, price_in_ticks);
+        let mut ladder = self.clone();
+        let levels = match my_side {
+            Side::Bid => &mut ladder.bids,
+            Side::Ask => &mut ladder.asks,
+        };
+        levels.insert(
+            my_index,
+            LadderOrder {
+                price_in_ticks,
+                size_in_base_lots: order.num_base_lots,
+            },
+        );
+        let fills = walk_levels(&ladder, side, size_in_lots);
+        // Only a taker on the other side of the book can reach the order
+        let my_base_lots_filled = if my_side == side {
+            0
+        } else {
+            fills
+                .iter()
+                .find(|fill| fill.level_index == my_index)
+                .map_or(0, |fill| fill.base_lots_filled)
+        };
+        (summarize_fills(&fills), my_base_lots_filled)
+    }
+
+    fn simulate_checkpoints(&self, side: Side, sizes: &[u64]) -> Vec<SimulationSummaryInLots> {
+        let levels = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+        let mut checkpoints = (0..sizes.len()).collect::<Vec<_>>();
+        checkpoints.sort_by_key(|&i| sizes[i]);
+
+        let mut summaries = vec![None; sizes.len()];
+        // Totals over the levels that every remaining checkpoint takes in full
+        let mut level_index = 0;
+        let mut full_base_lots = 0_u64;
+        let mut full_quote_lots = 0_u128;
+        for i in checkpoints {
+            let size_in_lots = sizes[i] as u128;
+            while let Some(level) = levels.get(level_index) {
+                let level_quote_lots =
+                    level.price_in_ticks as u128 * level.size_in_base_lots as u128;
+                let cost = match side {
+                    Side::Bid => full_quote_lots + level_quote_lots,
+                    Side::Ask => full_base_lots as u128 + level.size_in_base_lots as u128,
+                };
+                if cost > size_in_lots {
+                    break;
+                }
+                full_base_lots += level.size_in_base_lots;
+                full_quote_lots = full_quote_lots.saturating_add(level_quote_lots);
+                level_index += 1;
+            }
+            // The rest of the order partially fills the next level, and nothing after it
+            let (partial_base_lots, partial_quote_lots) = match levels.get(level_index) {
+                Some(level) => {
+                    let base_lots = match side {
+                        Side::Bid => {
+                            ((size_in_lots - full_quote_lots) / level.price_in_ticks as u128) as u64
+                        }
+                        Side::Ask => (size_in_lots - full_base_lots as u128) as u64,
+                    };
+                    (base_lots, base_lots as u128 * level.price_in_ticks as u128)
+                }
+                None => (0, 0),
+            };
+            summaries[i] = Some(SimulationSummaryInLots {
+                base_lots_filled: full_base_lots + partial_base_lots,
+                quote_lots_filled: u64::try_from(full_quote_lots + partial_quote_lots)
+                    .unwrap_or(u64::MAX),
+            });
+        }
+        summaries.into_iter().flatten().collect()
+    }
+
+    fn impact_adjusted_price_in_ticks(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        impact_coefficient: f64,
+    ) -> Option<f64> {
+        if !impact_coefficient.is_finite() || impact_coefficient < 0.0 {
+            return None;
+        }
+        let fills = walk_levels(self, side, size_in_lots);
+        let worst_price_in_ticks = fills.last()?.price_in_ticks;
+        let summary = summarize_fills(&fills);
+        let side_base_lots = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        }
+        .iter()
+        .fold(0_u64, |total, level| {
+            total.saturating_add(level.size_in_base_lots)
+        });
+        // Leftovers too small for another lot at the last level are not unfilled depth
+        let residual_base_lots = if summary.base_lots_filled < side_base_lots {
+            0
+        } else {
+            match side {
+                Side::Bid => {
+                    size_in_lots.saturating_sub(summary.quote_lots_filled) / worst_price_in_ticks
+                }
+                Side::Ask => size_in_lots.saturating_sub(summary.base_lots_filled),
+            }
+        };
+        let impact_in_ticks = impact_coefficient * (residual_base_lots as f64).sqrt();
+        let residual_price_in_ticks = match side {
+            Side::Bid => worst_price_in_ticks as f64 + impact_in_ticks,
+            Side::Ask => (worst_price_in_ticks as f64 - impact_in_ticks).max(0.0),
+        };
+        let quote_lots =
+            summary.quote_lots_filled as f64 + residual_base_lots as f64 * residual_price_in_ticks;
+        Some(quote_lots / (summary.base_lots_filled as f64 + residual_base_lots as f64))
+    }
+
+    fn recommended_packet(
+        &self,
+        side: Side,
+        size_in_lots: u64,
+        slippage_bps: u16,
+        client_order_id: u128,
+    ) -> Option<OrderPacket> {
+        let fills = walk_levels(self, side, size_in_lots);
+        let worst_price_in_ticks = fills.last()?.price_in_ticks;
+        let buffer_in_ticks = (worst_price_in_ticks as u128 * slippage_bps as u128 / 10_000) as u64;
+        let limit_price_in_ticks = match side {
+            Side::Bid => worst_price_in_ticks.saturating_add(buffer_in_ticks),
+            Side::Ask => worst_price_in_ticks.saturating_sub(buffer_in_ticks),
+        };
+        Some(OrderPacket::new_ioc_by_lots(
+            side,
+            limit_price_in_ticks,
+            summarize_fills(&fills).base_lots_filled,
+            SelfTradeBehavior::CancelProvide,
+            None,
+            client_order_id,
+            false,
+        ))
+    }
+}
+
+/// A `Ladder` tagged with the slot it was built at, for callers that cache ladders between fetches.
+/// The ladder is read through `Deref`/`AsRef` and only changed through `LadderUpdates`, so every
+/// change bumps `version` and drops the cached simulation.
+#[derive(Debug, Clone)]
+pub struct LadderSnapshot {
+    ladder: Ladder,
+    /// Slot the ladder was read at. A value of 0 means the slot is unknown.
+    pub build_slot: u64,
+    version: u64,
+    last_simulation: Option<CachedSimulation>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedSimulation {
+    side: Side,
+    size_in_lots: u64,
+    version: u64,
+    summary: SimulationSummaryInLots,
+}
+
+impl LadderSnapshot {
+    pub fn new(ladder: Ladder, build_slot: u64) -> Self {
+        Self {
+            ladder,
+            build_slot,
+            version: 0,
+            last_simulation: None,
+        }
+    }
+
+    /// Returns true if the ladder is more than `max_age_slots` slots older than `current_slot`.
+    /// Snapshots with an unknown build slot are always considered stale.
+    pub fn is_stale(&self, current_slot: u64, max_age_slots: u64) -> bool {
+        self.build_slot == 0 || current_slot.saturating_sub(self.build_slot) > max_age_slots
+    }
+
+    /// Number of updates applied since the snapshot was built, incremented on every
+    /// `apply_level_update`.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Bids, best (highest) price first.
+    pub fn bids(&self) -> &[LadderOrder] {
+        &self.ladder.bids
+    }
+
+    /// Asks, best (lowest) price first.
+    pub fn asks(&self) -> &[LadderOrder] {
+        &self.ladder.asks
+    }
+
+    /// Same as `MarketSimulator::simulate_market_sell`, but returns the previous result without
+    /// walking the book again if the side, size, and `version` are unchanged since the last call.
+    pub fn simulate_cached(&mut self, side: Side, size_in_lots: u64) -> SimulationSummaryInLots {
+        if let Some(cached) = &self.last_simulation {
+            if cached.side == side
+                && cached.size_in_lots == size_in_lots
+                && cached.version == self.version
+            {
+                return cached.summary.clone();
+            }
+        }
+        let summary = self.ladder.simulate_market_sell(side, size_in_lots);
+        self.last_simulation = Some(CachedSimulation {
+            side,
+            size_in_lots,
+            version: self.version,
+            summary: summary.clone(),
+        });
+        summary
+    }
+}
+
+impl From<Ladder> for LadderSnapshot {
+    fn from(ladder: Ladder) -> Self {
+        Self::new(ladder, 0)
+    }
+}
+
+impl AsRef<Ladder> for LadderSnapshot {
+    fn as_ref(&self) -> &Ladder {
+        &self.ladder
+    }
+}
+
+impl Deref for LadderSnapshot {
+    type Target = Ladder;
+
+    fn deref(&self) -> &Self::Target {
+        &self.ladder
+    }
+}
+
+/// A view of a `Ladder` in the caller's own lot sizes, where one caller base lot is
+/// `base_lot_scale` Phoenix base lots and one caller quote lot is `quote_lot_scale` Phoenix quote
+/// lots. A scale of 0 is treated as 1.
+///
+/// Inputs are multiplied up to Phoenix lots, saturating at `u64::MAX`. Outputs that don't divide
+/// evenly are rounded against the caller: base lots bought and quote lots received round down,
+/// while quote lots spent and base lots sold round up.
+#[derive(Debug, Clone, Copy)]
+pub struct LotScale<'a> {
+    ladder: &'a Ladder,
+    base_lot_scale: u64,
+    quote_lot_scale: u64,
+}
+
+impl<'a> LotScale<'a> {
+    pub fn new(ladder: &'a Ladder, base_lot_scale: u64, quote_lot_scale: u64) -> Self {
+        Self {
+            ladder,
+            base_lot_scale: base_lot_scale.max(1),
+            quote_lot_scale: quote_lot_scale.max(1),
+        }
+    }
+
+    /// Same as `MarketSimulator::simulate_market_sell` with the size and the result in the
+    /// caller's lots: quote lots for `Side::Bid` and base lots for `Side::Ask`.
+    pub fn simulate_market_sell(&self, side: Side, size_in_lots: u64) -> SimulationSummaryInLots {
+        let summary = match side {
+            Side::Bid => self
+                .ladder
+                .sell_quote(size_in_lots.saturating_mul(self.quote_lot_scale)),
+            Side::Ask => self
+                .ladder
+                .sell_base(size_in_lots.saturating_mul(self.base_lot_scale)),
+        };
+        match side {
+            Side::Bid => SimulationSummaryInLots {
+                base_lots_filled: summary.base_lots_filled / self.base_lot_scale,
+                quote_lots_filled: summary.quote_lots_filled.div_ceil(self.quote_lot_scale),
+            },
+            Side::Ask => SimulationSummaryInLots {
+                base_lots_filled: summary.base_lots_filled.div_ceil(self.base_lot_scale),
+                quote_lots_filled: summary.quote_lots_filled / self.quote_lot_scale,
+            },
+        }
+    }
+}
+
+/// Read-only measurements of the book. Unlike `MarketSimulator`, a `side` argument names the side
+/// of the book being measured, so `Side::Ask` reads the asks. Methods about an order of our own,
+/// such as `ticks_to_fill`, take the side that order rests on.
+pub trait LadderMetrics {
+    /// Suggests a resting ask price that covers `inventory_cost_in_ticks` plus a margin of
+    /// `target_margin_bps`, rounded up to a whole tick. The suggestion is clamped strictly inside
+    /// the spread, between one tick above the best bid and one tick below the best ask, so the
+    /// order rests at the top of the book without crossing. This means a break-even above the
+    /// best ask is priced below it; compare the result against the break-even to detect that. A
+    /// one tick spread has no price strictly inside it, so the best ask is returned to join that
+    /// level. A missing best bid leaves the price floored at 1 tick, and a missing best ask leaves
+    /// it uncapped.
+    fn break_even_ask_price_in_ticks(
+        &self,
+        inventory_cost_in_ticks: f64,
+        target_margin_bps: u16,
+    ) -> u64;
+
+    /// Returns `(price_in_ticks, cumulative_quote_lots)` for each level on `side` of the book,
+    /// where the notional is what it costs to take every level up to and including that one. The
+    /// curve a buyer pays is `cost_curve(Side::Ask)`.
+    /// Accumulating in `u128` keeps deep books from overflowing.
+    fn cost_curve(&self, side: Side) -> Vec<(u64, u128)>;
+
+    /// Returns `(base_lots, avg_price_in_ticks)` for taking `step_in_lots`, then twice that, and so
+    /// on from `side` of the book, where the price is the volume-weighted average of filling that
+    /// many base lots. Like `cost_curve`, `side` is the book side, so a buyer's curve is
+    /// `avg_price_curve(Side::Ask, ..)`. The curve is built in a single pass over the levels and
+    /// stops at the last step the book can fill completely. A `step_in_lots` of 0 is treated as 1.
+    fn avg_price_curve(&self, side: Side, step_in_lots: u64) -> Vec<(u64, f64)>;
+
+    /// Returns the price of the level on `side` of the book at which the cumulative base lots,
+    /// counted from the best price, first reach `percentile` of the side's total depth. For
+    /// example 0.5 gives the median-depth price. The percentile is clamped to `[0, 1]`, and 0
+    /// gives the best price. Returns None if `side` is empty.
+    fn price_at_depth_percentile(&self, side: Side, percentile: f64) -> Option<u64>;
+
+    /// Returns true if a stop order on `side` with the given trigger price would fire.
+    /// A sell stop (`Side::Ask`) triggers when the best bid falls to or below the trigger, and a
+    /// buy stop (`Side::Bid`) triggers when the best ask rises to or above it.
+    /// Returns false if the relevant side of the book is empty.
+    fn is_stop_triggered(&self, trigger_price_in_ticks: u64, side: Side) -> bool;
+
+    /// Returns true if `packet`'s price crosses the best price on the opposite side of the book:
+    /// a buy at or above the best ask, or a sell at or below the best bid. Only the price is
+    /// checked, so a crossing post-only packet counts as marketable even though it would not
+    /// take. An IOC packet without a price is marketable whenever the opposite side has orders.
+    /// Returns false if the opposite side of the book is empty.
+    fn is_marketable(&self, packet: &OrderPacket) -> bool;
+
+    /// Aggregates the levels on `side` into fixed-width price buckets, returning
+    /// `(bucket_floor_price_in_ticks, total_base_lots_in_bucket)` in book order.
+    /// A `bucket_size_in_ticks` of 0 is treated as 1, i.e. every tick is its own bucket.
+    fn bucketed(&self, side: Side, bucket_size_in_ticks: u64) -> Vec<(u64, u64)>;
+
+    /// Returns the spread between the best bid and best ask in basis points of the mid price.
+    /// Computed from tick prices, so it doesn't depend on the market's lot sizes.
+    /// Returns None if either side of the book is empty or the mid price is zero.
+    fn spread_bps(&self) -> Option<f64>;
+
+    /// Returns the size-weighted average price in ticks of every level on both sides of the book
+    /// combined, a whole-book fair value estimate. The weighting is accumulated in `u128`.
+    /// Returns None if the book is empty.
+    fn book_center_of_mass_in_ticks(&self) -> Option<f64>;
+
+    /// Returns the ratio of bid notional to ask notional, counting only levels within
+    /// `band_in_ticks` of the mid price (inclusive). Notionals are in quote lots, accumulated in
+    /// `u128`. Returns None if either side of the book is empty, or if no ask liquidity falls
+    /// within the band.
+    fn band_imbalance(&self, band_in_ticks: u64) -> Option<f64>;
+
+    /// Returns the best price on `side` of the book carried forward by `holding_days` at a simple
+    /// annual rate of `annual_rate_bps`, using a 365 day year. Returns None if the side is empty.
+    fn carry_adjusted_price_in_ticks(
+        &self,
+        side: Side,
+        annual_rate_bps: u16,
+        holding_days: f64,
+    ) -> Option<f64>;
+
+    /// Checks the invariants the simulations rely on: bids in descending price order, asks in
+    /// ascending price order, and every level with a non-zero price and size. Useful for
+    /// validating ladders reconstructed from a feed before simulating against them. Returns the
+    /// first broken invariant, checking the bids before the asks.
+    fn is_well_formed(&self) -> Result<(), LadderError>;
+
+    /// Shocks the mid price by `shock_bps` and returns the quote lot notional of the bids still
+    /// priced above the shocked price and of the asks still priced below it, as `(bids, asks)`.
+    /// Returns `(0, 0)` if either side of the book is empty.
+    fn value_at_price_shock(&self, shock_bps: i32) -> (u128, u128);
+
+    /// Compares this ladder to a newer snapshot `other`, reporting the levels added, removed,
+    /// and resized on each side.
+    fn diff(&self, other: &Ladder) -> LadderDiff;
+
+    /// Returns the index on `side` of the book at which a resting order priced at
+    /// `price_in_ticks` would be inserted, which is the number of strictly better-priced levels
+    /// ahead of it. An order at an existing price joins in front of that level's index. Use
+    /// `fifo_insertion_index` for where a new order actually queues.
+    fn insertion_index(&self, side: Side, price_in_ticks: u64) -> usize;
+
+    /// Returns the index on `side` of the book at which a new resting order priced at
+    /// `price_in_ticks` is queued under FIFO matching, which is the number of levels priced at
+    /// least as well as it. Unlike `insertion_index`, an order at an existing price goes behind
+    /// every level at that price, since those orders were placed first.
+    fn fifo_insertion_index(&self, side: Side, price_in_ticks: u64) -> usize;
+
+    /// Returns the levels on `side` of the book as phoenix's typed quantities, in book order.
+    fn typed_levels(&self, side: Side) -> Vec<(Ticks, BaseLots)>;
+
+    /// Returns how many ticks the best opposite price must move for an order resting on `side` at
+    /// `resting_price_in_ticks` to be reached: a resting bid fills when the asks fall to it, and a
+    /// resting ask fills when the bids rise to it. Returns `Some(0)` if the order is already
+    /// marketable and None if the opposite side of the book is empty.
+    fn ticks_to_fill(&self, side: Side, resting_price_in_ticks: u64) -> Option<u64>;
+
+    /// Advisory estimate of how many seconds it takes the book to reach an order resting on `side`
+    /// at `resting_price_in_ticks`, treating the best opposite price as a random walk with a
+    /// standard deviation of `tick_volatility_per_second` ticks per square root second. Uses the
+    /// diffusion time `(ticks_to_fill / tick_volatility_per_second)^2`, so an already marketable
+    /// order gives 0. Returns None if the opposite side is empty, or if the order is not
+    /// marketable and the volatility is not a positive finite number.
+    fn estimated_fill_seconds(
+        &self,
+        side: Side,
+        resting_price_in_ticks: u64,
+        tick_volatility_per_second: f64,
+    ) -> Option<f64>;
+
+    /// Returns the price one tick better than the best price on `side` of the book: the best bid
+    /// plus one tick for a new bid, or the best ask minus one tick for a new ask. The price is
+    /// clamped to one tick inside the best opposite price so it never crosses, which means it
+    /// joins the best price when the spread is a single tick. Ask prices never go below 1 tick.
+    /// Returns None if `side` is empty.
+    fn improve_price_in_ticks(&self, side: Side) -> Option<u64>;
+
+    /// Returns how many ticks an order resting on `side` at `my_current_price_in_ticks` must be
+    /// repriced by to be strictly better than the best price on `side`: one tick past the best
+    /// price, or 0 if the order is already at or better than it (an order at the best price may
+    /// be the best level itself). Whether the new price would cross the opposite side is not
+    /// checked. Returns None if `side` is empty.
+    fn ticks_to_become_best(&self, side: Side, my_current_price_in_ticks: u64) -> Option<u64>;
+
+    /// Returns how many base lots of an order resting on `side` at `price_in_ticks` fill when a
+    /// taker order of `incoming_take_base_lots` arrives. The taker first clears every
+    /// better-priced level on `side`, then the `my_queue_ahead_base_lots` queued in front of the
+    /// order at its price. The result is capped at the level's size less the lots ahead, since
+    /// the order is part of that level; callers should also cap it at their own order size.
+    /// Returns 0 if there is no level at `price_in_ticks`.
+    fn my_fill_at_level(
+        &self,
+        side: Side,
+        price_in_ticks: u64,
+        my_queue_ahead_base_lots: u64,
+        incoming_take_base_lots: u64,
+    ) -> u64;
+}
+
+impl LadderMetrics for Ladder {
+    fn break_even_ask_price_in_ticks(
+        &self,
+        inventory_cost_in_ticks: f64,
+        target_margin_bps: u16,
+    ) -> u64 {
+        let break_even = (inventory_cost_in_ticks.max(0.0)
+            * (1.0 + target_margin_bps as f64 / 10_000.0))
+            .ceil() as u64;
+        let lowest_price = self
+            .bids
+            .first()
+            .map_or(1, |best_bid| best_bid.price_in_ticks.saturating_add(1));
+        let best_ask = match self.asks.first() {
+            Some(best_ask) => best_ask.price_in_ticks,
+            None => return break_even.max(lowest_price),
+        };
+        let highest_price = best_ask.saturating_sub(1);
+        if lowest_price > highest_price {
+            return best_ask;
+        }
+        break_even.clamp(lowest_price, highest_price)
+    }
+
+    fn cost_curve(&self, side: Side) -> Vec<(u64, u128)> {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        levels
+            .iter()
+            .scan(0_u128, |cumulative_quote_lots, level| {
+                *cumulative_quote_lots = cumulative_quote_lots
+                    .saturating_add(level.price_in_ticks as u128 * level.size_in_base_lots as u128);
+                Some((level.price_in_ticks, *cumulative_quote_lots))
+            })
+            .collect()
+    }
+
+    fn avg_price_curve(&self, side: Side, step_in_lots: u64) -> Vec<(u64, f64)> {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let step_in_lots = step_in_lots.max(1);
+        let mut curve = vec![];
+        let mut base_lots = 0_u64;
+        let mut quote_lots = 0_u128;
+        let mut target_base_lots = step_in_lots;
+        for level in levels.iter() {
+            let mut remaining_in_level = level.size_in_base_lots;
+            while remaining_in_level > 0 {
+                let lots_taken = (target_base_lots - base_lots).min(remaining_in_level);
+                base_lots += lots_taken;
+                quote_lots =
+                    quote_lots.saturating_add(lots_taken as u128 * level.price_in_ticks as u128);
+                remaining_in_level -= lots_taken;
+                if base_lots == target_base_lots {
+                    curve.push((base_lots, quote_lots as f64 / base_lots as f64));
+                    match target_base_lots.checked_add(step_in_lots) {
+                        Some(next_target) => target_base_lots = next_target,
+                        None => return curve,
+                    }
+                }
+            }
+        }
+        curve
+    }
+
+    fn price_at_depth_percentile(&self, side: Side, percentile: f64) -> Option<u64> {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let total_base_lots = levels
+            .iter()
+            .map(|level| level.size_in_base_lots as u128)
+            .sum::<u128>();
+        let percentile = if percentile.is_nan() {
+            0.0
+        } else {
+            percentile.clamp(0.0, 1.0)
+        };
+        let target_base_lots =
+            ((total_base_lots as f64 * percentile).ceil() as u128).clamp(1, total_base_lots.max(1));
+        let mut cumulative_base_lots = 0_u128;
+        levels
+            .iter()
+            .find(|level| {
+                cumulative_base_lots += level.size_in_base_lots as u128;
+                cumulative_base_lots >= target_base_lots
+            })
+            .or(levels.last())
+            .map(|level| level.price_in_ticks)
+    }
+
+    fn is_stop_triggered(&self, trigger_price_in_ticks: u64, side: Side) -> bool {
+        match side {
+            Side::Bid => self
+                .asks
+                .first()
+                .is_some_and(|best_ask| best_ask.price_in_ticks >= trigger_price_in_ticks),
+            Side::Ask => self
+                .bids
+                .first()
+                .is_some_and(|best_bid| best_bid.price_in_ticks <= trigger_price_in_ticks),
+        }
+    }
+
+    fn is_marketable(&self, packet: &OrderPacket) -> bool {
+        let price_in_ticks = packet.get_price_in_ticks().as_u64();
+        match packet.side() {
+            Side::Bid => self
+                .asks
+                .first()
+                .is_some_and(|best_ask| price_in_ticks >= best_ask.price_in_ticks),
+            Side::Ask => self
+                .bids
+                .first()
+                .is_some_and(|best_bid| price_in_ticks <= best_bid.price_in_ticks),
+        }
+    }
+
+    fn bucketed(&self, side: Side, bucket_size_in_ticks: u64) -> Vec<(u64, u64)> {
+        let bucket_size_in_ticks = bucket_size_in_ticks.max(1);
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let mut buckets: Vec<(u64, u64)> = vec![];
+        for level in levels.iter() {
+            let bucket_floor = level.price_in_ticks / bucket_size_in_ticks * bucket_size_in_ticks;
+            match buckets.last_mut() {
+                Some((floor, size)) if *floor == bucket_floor => {
+                    *size = size.saturating_add(level.size_in_base_lots)
+                }
+                _ => buckets.push((bucket_floor, level.size_in_base_lots)),
+            }
+        }
+        buckets
+    }
+
+    fn spread_bps(&self) -> Option<f64> {
+        let best_bid = self.bids.first()?.price_in_ticks as f64;
+        let best_ask = self.asks.first()?.price_in_ticks as f64;
+        let mid = (best_bid + best_ask) / 2.0;
+        if mid == 0.0 {
+            return None;
+        }
+        Some((best_ask - best_bid) / mid * 10_000.0)
+    }
+
+    fn book_center_of_mass_in_ticks(&self) -> Option<f64> {
+        let (base_lots, quote_lots) = self.bids.iter().chain(self.asks.iter()).fold(
+            (0_u128, 0_u128),
+            |(base_lots, quote_lots), level| {
+                (
+                    base_lots.saturating_add(level.size_in_base_lots as u128),
+                    quote_lots.saturating_add(
+                        level.price_in_ticks as u128 * level.size_in_base_lots as u128,
+                    ),
+                )
+            },
+        );
+        if base_lots == 0 {
+            return None;
+        }
+        Some(quote_lots as f64 / base_lots as f64)
+    }
+
+    fn band_imbalance(&self, band_in_ticks: u64) -> Option<f64> {
+        // Work in doubled ticks so a mid between two ticks stays exact
+        let double_mid =
+            self.bids.first()?.price_in_ticks as u128 + self.asks.first()?.price_in_ticks as u128;
+        let double_band = 2 * band_in_ticks as u128;
+        let band_notional = |levels: &[LadderOrder], in_band: &dyn Fn(u128) -> bool| {
+            levels
+                .iter()
+                .take_while(|level| in_band(2 * level.price_in_ticks as u128))
+                .fold(0_u128, |notional, level| {
+                    notional.saturating_add(
+                        level.price_in_ticks as u128 * level.size_in_base_lots as u128,
+                    )
+                })
+        };
+        let bid_notional = band_notional(&self.bids, &|double_price| {
+            double_price + double_band >= double_mid
+        });
+        let ask_notional = band_notional(&self.asks, &|double_price| {
+            double_price <= double_mid + double_band
+        });
+        if ask_notional == 0 {
+            return None;
+        }
+        Some(bid_notional as f64 / ask_notional as f64)
+    }
+
+    fn carry_adjusted_price_in_ticks(
+        &self,
+        side: Side,
+        annual_rate_bps: u16,
+        holding_days: f64,
+    ) -> Option<f64> {
+        let best_price = match side {
+            Side::Bid => self.bids.first()?,
+            Side::Ask => self.asks.first()?,
+        }
+        .price_in_ticks as f64;
+        let carry = annual_rate_bps as f64 / 10_000.0 * holding_days / 365.0;
+        Some(best_price * (1.0 + carry))
+    }
+
+    fn is_well_formed(&self) -> Result<(), LadderError> {
+        for (side, levels) in [(Side::Bid, &self.bids), (Side::Ask, &self.asks)] {
+            for (index, level) in levels.iter().enumerate() {
+                if level.price_in_ticks == 0 {
+                    return Err(LadderError::ZeroPrice { side, index });
+                }
+                if level.size_in_base_lots == 0 {
+                    return Err(LadderError::ZeroSize { side, index });
+                }
+                if let Some(previous) = index.checked_sub(1).map(|j| &levels[j]) {
+                    if is_better_price(side, level.price_in_ticks, previous.price_in_ticks) {
+                        return Err(LadderError::OutOfOrder {
+                            side,
+                            index,
+                            price_in_ticks: level.price_in_ticks,
+                            previous_price_in_ticks: previous.price_in_ticks,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn value_at_price_shock(&self, shock_bps: i32) -> (u128, u128) {
+        let (best_bid, best_ask) = match (self.bids.first(), self.asks.first()) {
+            (Some(best_bid), Some(best_ask)) => (best_bid, best_ask),
+            _ => return (0, 0),
+        };
+        let mid = (best_bid.price_in_ticks as f64 + best_ask.price_in_ticks as f64) / 2.0;
+        let shocked_price = mid * (1.0 + shock_bps as f64 / 10_000.0);
+        let notional = |levels: &[LadderOrder], survives: &dyn Fn(f64) -> bool| {
+            levels
+                .iter()
+                .filter(|level| survives(level.price_in_ticks as f64))
+                .map(|level| level.price_in_ticks as u128 * level.size_in_base_lots as u128)
+                .fold(0_u128, |total, quote_lots| total.saturating_add(quote_lots))
+        };
+        (
+            notional(&self.bids, &|price| price > shocked_price),
+            notional(&self.asks, &|price| price < shocked_price),
+        )
+    }
+
+    fn diff(&self, other: &Ladder) -> LadderDiff {
+        LadderDiff {
+            bids: diff_levels(Side::Bid, &self.bids, &other.bids),
+            asks: diff_levels(Side::Ask, &self.asks, &other.asks),
+        }
+    }
+
+    fn insertion_index(&self, side: Side, price_in_ticks: u64) -> usize {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        levels.partition_point(|level| is_better_price(side, level.price_in_ticks, price_in_ticks))
+    }
+
+    fn fifo_insertion_index(&self, side: Side, price_in_ticks: u64) -> usize {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        levels.partition_point(|level| !is_better_price(side, price_in_ticks, level.price_in_ticks))
+    }
+
+    fn typed_levels(&self, side: Side) -> Vec<(Ticks, BaseLots)> {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        levels
+            .iter()
+            .map(|level| {
+                (
+                    Ticks::new(level.price_in_ticks),
+                    BaseLots::new(level.size_in_base_lots),
+                )
+            })
+            .collect()
+    }
+
+    fn ticks_to_fill(&self, side: Side, resting_price_in_ticks: u64) -> Option<u64> {
+        match side {
+            Side::Bid => self.asks.first().map(|best_ask| {
+                best_ask
+                    .price_in_ticks
+                    .saturating_sub(resting_price_in_ticks)
+            }),
+            Side::Ask => self
+                .bids
+                .first()
+                .map(|best_bid| resting_price_in_ticks.saturating_sub(best_bid.price_in_ticks)),
+        }
+    }
+
+    fn estimated_fill_seconds(
+        &self,
+        side: Side,
+        resting_price_in_ticks: u64,
+        tick_volatility_per_second: f64,
+    ) -> Option<f64> {
+        let ticks_to_fill = self.ticks_to_fill(side, resting_price_in_ticks)?;
+        if ticks_to_fill == 0 {
+            return Some(0.0);
+        }
+        if !tick_volatility_per_second.is_finite() || tick_volatility_per_second <= 0.0 {
+            return None;
+        }
+        Some((ticks_to_fill as f64 / tick_volatility_per_second).powi(2))
+    }
+
+    fn improve_price_in_ticks(&self, side: Side) -> Option<u64> {
+        match side {
+            Side::Bid => {
+                let improved = self.bids.first()?.price_in_ticks.saturating_add(1);
+                Some(match self.asks.first() {
+                    Some(best_ask) => improved.min(best_ask.price_in_ticks.saturating_sub(1)),
+                    None => improved,
+                })
+            }
+            Side::Ask => {
+                let improved = self.asks.first()?.price_in_ticks.saturating_sub(1).max(1);
+                Some(match self.bids.first() {
+                    Some(best_bid) => improved.max(best_bid.price_in_ticks.saturating_add(1)),
+                    None => improved,
+                })
+            }
+        }
+    }
+
+    fn ticks_to_become_best(&self, side: Side, my_current_price_in_ticks: u64) -> Option<u64> {
+        let best_price_in_ticks = match side {
+            Side::Bid => self.bids.first()?.price_in_ticks,
+            Side::Ask => self.asks.first()?.price_in_ticks,
+        };
+        if !is_better_price(side, best_price_in_ticks, my_current_price_in_ticks) {
+            return Some(0);
+        }
+        Some(
+            best_price_in_ticks
+                .abs_diff(my_current_price_in_ticks)
+                .saturating_add(1),
+        )
+    }
+
+    fn my_fill_at_level(
+        &self,
+        side: Side,
+        price_in_ticks: u64,
+        my_queue_ahead_base_lots: u64,
+        incoming_take_base_lots: u64,
+    ) -> u64 {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let (better_base_lots, level_base_lots) =
+            levels
+                .iter()
+                .fold((0_u64, 0_u64), |(better, at_price), level| {
+                    if is_better_price(side, level.price_in_ticks, price_in_ticks) {
+                        (better.saturating_add(level.size_in_base_lots), at_price)
+                    } else if level.price_in_ticks == price_in_ticks {
+                        (better, at_price.saturating_add(level.size_in_base_lots))
+                    } else {
+                        (better, at_price)
+                    }
+                });
+        incoming_take_base_lots
+            .saturating_sub(better_base_lots)
+            .saturating_sub(my_queue_ahead_base_lots)
+            .min(level_base_lots.saturating_sub(my_queue_ahead_base_lots))
+    }
+}
+
+/// Simulates a market order against an order-id-aware `Orderbook` (such as
+/// `MarketState::orderbook`), modeling how the matching engine treats the resting orders for which
+/// `is_my_order` returns true:
+/// * `Abort` voids the whole order as soon as it would take at least one lot from one of your
+///   orders.
+/// * `CancelProvide` cancels your resting orders and keeps matching behind them.
+/// * `DecrementTake` shrinks the taker order by the overlapping size without trading.
+///
+/// `size_in_lots` is in quote lots for `Side::Bid` and base lots for `Side::Ask`, matching
+/// `MarketSimulator::simulate_market_sell`. `FIFOOrderId` is not `Hash`, so keep your order ids
+/// in a `BTreeSet` and pass `|order_id| my_order_ids.contains(order_id)`.
+pub fn simulate_with_self_trades(
+    orderbook: &Orderbook<FIFOOrderId, PhoenixOrder>,
+    side: Side,
+    size_in_lots: u64,
+    is_my_order: impl Fn(&FIFOOrderId) -> bool,
+    self_trade_behavior: SelfTradeBehavior,
+) -> SimulationSummaryInLots {
+    // FIFOOrderId sorts both sides of the book in matching priority
+    let book = match side {
+        Side::Bid => &orderbook.asks,
+        Side::Ask => &orderbook.bids,
+    };
+    let mut remaining_lots = size_in_lots;
+    let mut base_lots_filled = 0;
+    let mut quote_lots_filled = 0_u128;
+    for (order_id, order) in book.iter() {
+        if remaining_lots == 0 {
+            break;
+        }
+        let price_in_ticks = order_id.price_in_ticks.as_u64();
+        let base_lots = match side {
+            Side::Bid => remaining_lots / price_in_ticks,
+            Side::Ask => remaining_lots,
+        }
+        .min(order.num_base_lots);
+        let lots_used = match side {
+            Side::Bid => base_lots * price_in_ticks,
+            Side::Ask => base_lots,
+        };
+        if is_my_order(order_id) {
+            match self_trade_behavior {
+                // The taker only matches an order it can take at least one lot from
+                SelfTradeBehavior::Abort if base_lots > 0 => {
+                    return SimulationSummaryInLots {
+                        base_lots_filled: 0,
+                        quote_lots_filled: 0,
+                    }
+                }
+                SelfTradeBehavior::Abort | SelfTradeBehavior::CancelProvide => {}
+                SelfTradeBehavior::DecrementTake => remaining_lots -= lots_used,
+            }
+            continue;
+        }
+        base_lots_filled += base_lots;
+        quote_lots_filled =
+            quote_lots_filled.saturating_add(base_lots as u128 * price_in_ticks as u128);
+        remaining_lots -= lots_used;
+    }
+    SimulationSummaryInLots {
+        base_lots_filled,
+        quote_lots_filled: u64::try_from(quote_lots_filled).unwrap_or(u64::MAX),
+    }
+}
+
+/// Simulates a market order against an order-id-aware `Orderbook` (such as the one built by
+/// `Orderbook::from_market`) and returns the base lots taken from each maker.
+///
+/// `size_in_lots` is in quote lots for `Side::Bid` and base lots for `Side::Ask`, matching
+/// `MarketSimulator::simulate_market_sell`.
+pub fn simulate_by_trader(
+    orderbook: &Orderbook<FIFOOrderId, PhoenixOrder>,
+    side: Side,
+    size_in_lots: u64,
+) -> HashMap<Pubkey, u64> {
+    let book = match side {
+        Side::Bid => &orderbook.asks,
+        Side::Ask => &orderbook.bids,
+    };
+    let mut remaining_lots = size_in_lots;
+    let mut base_lots_by_trader = HashMap::new();
+    for (order_id, order) in book.iter() {
+        if remaining_lots == 0 {
+            break;
+        }
+        let price_in_ticks = order_id.price_in_ticks.as_u64();
+        let base_lots = match side {
+            Side::Bid => remaining_lots / price_in_ticks,
+            Side::Ask => remaining_lots,
+        }
+        .min(order.num_base_lots);
+        if base_lots == 0 {
+            continue;
+        }
+        *base_lots_by_trader.entry(order.maker_id).or_insert(0) += base_lots;
+        remaining_lots -= match side {
+            Side::Bid => base_lots * price_in_ticks,
+            Side::Ask => base_lots,
+        };
+    }
+    base_lots_by_trader
+}
+
+/// Returns the base lots on each side of `unfiltered` that are missing from `filtered`, as
+/// `(bids, asks)`. Levels are matched by price as in `LadderMetrics::diff`.
+pub fn expiring_base_lots(unfiltered: &Ladder, filtered: &Ladder) -> (u64, u64) {
+    let LadderDiff { bids, asks } = unfiltered.diff(filtered);
+    let missing = |side_diff: LadderSideDiff| {
+        side_diff
+            .removed
+            .iter()
+            .map(|level| level.size_in_base_lots)
+            .chain(
+                side_diff
+                    .resized
+                    .iter()
+                    .map(|&(_, old_size, new_size)| old_size.saturating_sub(new_size)),
+            )
+            .fold(0_u64, |total, size| total.saturating_add(size))
+    };
+    (missing(bids), missing(asks))
+}
+
+/// Returns the base lots on each side of `market`, as `(bids, asks)`, that rest on the book now
+/// but would be dropped as expired at the given slot and unix timestamp.
+pub fn expiring_liquidity(
+    market: &dyn Market<Pubkey, FIFOOrderId, FIFORestingOrder, OrderPacket>,
+    last_valid_slot: Option<u64>,
+    last_valid_unix_timestamp_in_seconds: Option<u64>,
+) -> (u64, u64) {
+    let unfiltered = market.get_ladder(u64::MAX);
+    let filtered = market.get_ladder_with_expiration(
+        u64::MAX,
+        last_valid_slot,
+        last_valid_unix_timestamp_in_seconds,
+    );
+    expiring_base_lots(&unfiltered, &filtered)
+}
+
+/// Simulates the same market order against each `(weight, ladder)` scenario and returns the
+/// weighted average fill, rounded to the nearest lot. Weights are normalized by their sum, so they
+/// don't need to add up to 1. Negative and non-finite weights count as 0, and if no scenario has a
+/// positive weight nothing fills.
+pub fn expected_fill(
+    scenarios: &[(f64, &Ladder)],
+    side: Side,
+    size_in_lots: u64,
+) -> SimulationSummaryInLots {
+    let mut total_weight = 0.0;
+    let mut base_lots = 0.0;
+    let mut quote_lots = 0.0;
+    for &(weight, ladder) in scenarios.iter() {
+        if !weight.is_finite() || weight <= 0.0 {
+            continue;
+        }
+        let summary = ladder.simulate_market_sell(side, size_in_lots);
+        total_weight += weight;
+        base_lots += weight * summary.base_lots_filled as f64;
+        quote_lots += weight * summary.quote_lots_filled as f64;
+    }
+    if total_weight == 0.0 {
+        return SimulationSummaryInLots {
+            base_lots_filled: 0,
+            quote_lots_filled: 0,
+        };
+    }
+    SimulationSummaryInLots {
+        base_lots_filled: (base_lots / total_weight).round() as u64,
+        quote_lots_filled: (quote_lots / total_weight).round() as u64,
+    }
+}
+
+/// Returns the quote lot profit of buying `base_lots` at `buy_price_in_ticks` and selling them at
+/// `sell_price_in_ticks`, using the same lot math as the simulations. Losses are negative, and
+/// results beyond the range of an `i64` saturate.
+pub fn realized_spread_capture(
+    buy_price_in_ticks: u64,
+    sell_price_in_ticks: u64,
+    base_lots: u64,
+) -> i64 {
+    let profit = (sell_price_in_ticks as i128 - buy_price_in_ticks as i128)
+        .saturating_mul(base_lots as i128);
+    profit.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+pub trait LadderUpdates {
+    /// Sets the total size resting at `price_in_ticks` on `side`, inserting a new level, resizing
+    /// an existing one, or removing it when `new_size_in_base_lots` is 0. The side stays sorted
+    /// (descending bids, ascending asks), and any duplicate levels at the price are merged.
+    fn apply_level_update(&mut self, side: Side, price_in_ticks: u64, new_size_in_base_lots: u64);
+
+    /// Returns a copy with the total size at `price_in_ticks` on `side` reduced by
+    /// `reduce_base_lots`, removing the level if nothing is left, for previewing the book after a
+    /// partial cancel. The reduced level is merged like `apply_level_update`. If there is no level
+    /// at the price the copy is unchanged.
+    fn with_level_reduced(&self, side: Side, price_in_ticks: u64, reduce_base_lots: u64) -> Self
+    where
+        Self: Sized;
+}
+
+/// Returns the total size of every level at `price_in_ticks` on `side`.
+fn level_size_in_base_lots(ladder: &Ladder, side: Side, price_in_ticks: u64) -> u64 {
+    let levels = match side {
+        Side::Bid => &ladder.bids,
+        Side::Ask => &ladder.asks,
+    };
+    levels
+        .iter()
+        .filter(|level| level.price_in_ticks == price_in_ticks)
+        .fold(0_u64, |total, level| {
+            total.saturating_add(level.size_in_base_lots)
+        })
+}
+
+impl LadderUpdates for Ladder {
+    fn apply_level_update(&mut self, side: Side, price_in_ticks: u64, new_size_in_base_lots: u64) {
+        let levels = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        let start = levels
+            .partition_point(|level| is_better_price(side, level.price_in_ticks, price_in_ticks));
+        let end = start
+            + levels[start..]
+                .iter()
+                .take_while(|level| level.price_in_ticks == price_in_ticks)
+                .count();
+        let replacement = (new_size_in_base_lots > 0).then_some(LadderOrder {
+            price_in_ticks,
+            size_in_base_lots: new_size_in_base_lots,
+        });
+        levels.splice(start..end, replacement);
+    }
+
+    fn with_level_reduced(&self, side: Side, price_in_ticks: u64, reduce_base_lots: u64) -> Self {
+        let mut ladder = self.clone();
+        let size_in_base_lots = level_size_in_base_lots(self, side, price_in_ticks);
+        if size_in_base_lots > 0 {
+            ladder.apply_level_update(
+                side,
+                price_in_ticks,
+                size_in_base_lots.saturating_sub(reduce_base_lots),
+            );
+        }
+        ladder
+    }
+}
+
+impl LadderUpdates for LadderSnapshot {
+    fn apply_level_update(&mut self, side: Side, price_in_ticks: u64, new_size_in_base_lots: u64) {
+        self.ladder
+            .apply_level_update(side, price_in_ticks, new_size_in_base_lots);
+        self.version += 1;
+        self.last_simulation = None;
+    }
+
+    fn with_level_reduced(&self, side: Side, price_in_ticks: u64, reduce_base_lots: u64) -> Self {
+        let mut snapshot = self.clone();
+        let size_in_base_lots = level_size_in_base_lots(&self.ladder, side, price_in_ticks);
+        if size_in_base_lots > 0 {
+            snapshot.apply_level_update(
+                side,
+                price_in_ticks,
+                size_in_base_lots.saturating_sub(reduce_base_lots),
+            );
+        }
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{ladder_from_levels, sol_usdc_ladder, sol_usdc_metadata};
+    use std::collections::BTreeSet;
+
+    struct Fixture {
+        pub ladder: Ladder,
+        pub atoms_in_base_lot: f64,
+        pub atoms_in_quote_lot: f64,
+        pub atoms_in_base_unit: f64,
+        pub atoms_in_quote_unit: f64,
+    }
+
+    // This is a very simplified ladder for SOL/USDC on Phoenix
+    fn get_sol_usdc_ladder() -> Fixture {
+        let ladder = sol_usdc_ladder();
+        let fixture = Fixture {
+            ladder,
+            atoms_in_base_lot: 1e6,
+            atoms_in_quote_lot: 1.,
+            atoms_in_base_unit: 1e9,
+            atoms_in_quote_unit: 1e6,
+        };
+        fixture
+    }
+
+    fn lots_to_unit_amount(lots: u64, lots_to_atoms: f64, atoms_to_unit: f64) -> f64 {
+        let atoms = lots_to_atoms * lots as f64;
+        let unit = atoms / atoms_to_unit;
+        unit
+    }
+
+    #[test]
+    fn test_empty_ladder_sell() {
+        let ladder = Ladder {
+            bids: vec![],
+            asks: vec![],
+        };
+        let result = ladder.simulate_market_sell(Side::Ask, 1000);
+        assert_eq!(result.base_lots_filled, 0);
+        assert_eq!(result.quote_lots_filled, 0);
+    }
+
+    #[test]
+    fn test_sell_more_than_available() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+
+        // Compute the max lots you can sell
+        let max_lots_purchaseable: u64 = ladder.bids.iter().map(|bid| bid.size_in_base_lots).sum();
+
+        // Sell twice as much, and assert that only the max is filled
+        let to_purchase = max_lots_purchaseable * 2;
+        let result = ladder.simulate_market_sell(Side::Ask, to_purchase);
+        assert_eq!(result.base_lots_filled, max_lots_purchaseable);
+        assert!(result.quote_lots_filled > 0);
+    }
+
+    #[test]
+    fn test_buy_more_than_available() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+
+        // Compute the max lots you can buy (from available asks)
+        let max_lots_sellable: u64 = ladder
+            .asks
+            .iter()
+            .map(|ask| ask.size_in_base_lots * ask.price_in_ticks)
+            .sum();
+
+        // Try to buy twice as much, which means you are selling twice as much base
+        let to_sell = max_lots_sellable * 2;
+        let result = ladder.simulate_market_sell(Side::Bid, to_sell);
+
+        assert_eq!(result.quote_lots_filled, max_lots_sellable);
+        assert!(result.quote_lots_filled > 0);
+    }
+
+    #[test]
+    fn test_simulate_buy_quote_ui() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let metadata = sol_usdc_metadata();
+        // 68 USDC with 1 atom quote lots and 6 decimals is 68_000_000 quote lots
+        let result = ladder.simulate_buy_quote_ui(68.0, &metadata);
+        let expected = ladder.sell_quote(68_000_000);
+        assert_eq!(result.base_lots_filled, expected.base_lots_filled);
+        assert_eq!(result.quote_lots_filled, expected.quote_lots_filled);
+
+        // Partial lots are rounded down
+        let metadata = MarketMetadata {
+            quote_atoms_per_quote_lot: 10,
+            ..metadata
+        };
+        let result = ladder.simulate_buy_quote_ui(0.0000159, &metadata);
+        assert_eq!(result.base_lots_filled, 0);
+        assert_eq!(result.quote_lots_filled, 0);
+    }
+
+    #[test]
+    fn test_break_even_ask_price_in_ticks() {
+        let ladder = ladder_from_levels(&[(10000, 10)], &[(11000, 10)]);
+        // 10000 ticks plus 500 bps is 10500 ticks, which is inside the spread
+        assert_eq!(ladder.break_even_ask_price_in_ticks(10000.0, 500), 10500);
+        // A break-even above the best ask is pulled to one tick inside it
+        assert_eq!(ladder.break_even_ask_price_in_ticks(12000.0, 0), 10999);
+        // A break-even at or below the best bid is lifted to one tick above it, so it doesn't cross
+        assert_eq!(ladder.break_even_ask_price_in_ticks(9000.0, 0), 10001);
+        assert_eq!(ladder.break_even_ask_price_in_ticks(10000.0, 0), 10001);
+
+        // With a one tick spread the only resting price at the top of the book is the best ask
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        assert_eq!(ladder.break_even_ask_price_in_ticks(20000.0, 50), 0x58c0);
+        assert_eq!(ladder.break_even_ask_price_in_ticks(23000.0, 0), 0x58c0);
+
+        let bids_only = ladder_from_levels(&[(10000, 10)], &[]);
+        assert_eq!(bids_only.break_even_ask_price_in_ticks(9000.0, 0), 10001);
+        assert_eq!(bids_only.break_even_ask_price_in_ticks(12000.0, 0), 12000);
+
+        let empty = Ladder {
+            bids: vec![],
+            asks: vec![],
+        };
+        assert_eq!(empty.break_even_ask_price_in_ticks(23000.0, 100), 23230);
+    }
+
+    #[test]
+    fn test_simulate_with_fill_counts() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+
+        // 0x043f lots fully takes the first bid, the next 1 lot takes part of the second
+        let (summary, counts) = ladder.simulate_with_fill_counts(Side::Ask, 0x043f + 1);
+        assert_eq!(summary.base_lots_filled, 0x043f + 1);
+        assert_eq!(
+            counts,
+            FillCounts {
+                fully_consumed_levels: 1,
+                partially_consumed_levels: 1,
+            }
+        );
+        let expected = ladder.simulate_market_sell(Side::Ask, 0x043f + 1);
+        assert_eq!(summary.quote_lots_filled, expected.quote_lots_filled);
+
+        // Buying more than the whole book consumes every level
+        let (summary, counts) = ladder.simulate_with_fill_counts(Side::Bid, u64::MAX / 2);
+        assert_eq!(counts.fully_consumed_levels, 3);
+        assert_eq!(counts.partially_consumed_levels, 0);
+        assert_eq!(
+            summary.base_lots_filled,
+            ladder
+                .asks
+                .iter()
+                .map(|ask| ask.size_in_base_lots)
+                .sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn test_cost_curve() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let curve = ladder.cost_curve(Side::Bid);
+        assert_eq!(curve.len(), 3);
+        assert_eq!(curve[0], (0x58bf, 0x58bf * 0x043f));
+        assert_eq!(curve[2].1, (0x58bf + 0x58b9 + 0x58a7) * 0x043f);
+
+        let deep = Ladder {
+            bids: vec![],
+            asks: vec![
+                LadderOrder {
+                    price_in_ticks: u64::MAX,
+                    size_in_base_lots: u64::MAX,
+                },
+                LadderOrder {
+                    price_in_ticks: u64::MAX,
+                    size_in_base_lots: 1,
+                },
+            ],
+        };
+        let curve = deep.cost_curve(Side::Ask);
+        assert_eq!(
+            curve[1].1,
+            u64::MAX as u128 * u64::MAX as u128 + u64::MAX as u128
+        );
+    }
+
+    #[test]
+    fn test_avg_price_curve() {
+        let ladder = ladder_from_levels(&[(100, 5), (90, 5)], &[(100, 5), (110, 5)]);
+        assert_eq!(
+            ladder.avg_price_curve(Side::Ask, 4),
+            vec![(4, 100.0), (8, (500.0 + 3.0 * 110.0) / 8.0)]
+        );
+        assert_eq!(
+            ladder.avg_price_curve(Side::Bid, 5),
+            vec![(5, 100.0), (10, 95.0)]
+        );
+        assert_eq!(ladder.avg_price_curve(Side::Bid, 0).len(), 10);
+        assert!(ladder.avg_price_curve(Side::Bid, 11).is_empty());
+
+        // Each point matches a from-scratch simulation of that size
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        for (base_lots, avg_price) in ladder.avg_price_curve(Side::Bid, 1000) {
+            let summary = ladder.sell_base(base_lots);
+            assert_eq!(summary.base_lots_filled, base_lots);
+            assert_eq!(
+                avg_price,
+                summary.quote_lots_filled as f64 / base_lots as f64
+            );
+        }
+    }
+
+    #[test]
+    fn test_price_at_depth_percentile() {
+        let ladder = ladder_from_levels(&[(100, 10), (99, 30), (98, 60)], &[(101, 50), (102, 50)]);
+        assert_eq!(ladder.price_at_depth_percentile(Side::Bid, 0.0), Some(100));
+        assert_eq!(ladder.price_at_depth_percentile(Side::Bid, 0.1), Some(100));
+        assert_eq!(ladder.price_at_depth_percentile(Side::Bid, 0.11), Some(99));
+        assert_eq!(ladder.price_at_depth_percentile(Side::Bid, 0.5), Some(98));
+        assert_eq!(ladder.price_at_depth_percentile(Side::Bid, 2.0), Some(98));
+        assert_eq!(ladder.price_at_depth_percentile(Side::Ask, 0.5), Some(101));
+        assert_eq!(ladder.price_at_depth_percentile(Side::Ask, 0.51), Some(102));
+        assert_eq!(ladder.price_at_depth_percentile(Side::Ask, -1.0), Some(101));
+        assert_eq!(
+            ladder_from_levels(&[], &[]).price_at_depth_percentile(Side::Ask, 0.5),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ladder_snapshot_staleness() {
+        let snapshot = LadderSnapshot::new(sol_usdc_ladder(), 1000);
+        assert!(!snapshot.is_stale(1000, 10));
+        assert!(!snapshot.is_stale(1010, 10));
+        assert!(snapshot.is_stale(1011, 10));
+        // Simulation is available through the underlying ladder
+        assert_eq!(snapshot.sell_base(1).base_lots_filled, 1);
+
+        let unknown_slot = LadderSnapshot::from(sol_usdc_ladder());
+        assert!(unknown_slot.is_stale(0, u64::MAX));
+    }
+
+    #[test]
+    fn test_simulate_cached() {
+        let mut snapshot = LadderSnapshot::new(sol_usdc_ladder(), 100);
+        let first = snapshot.simulate_cached(Side::Ask, 3000);
+        assert_eq!(first.base_lots_filled, 3000);
+        assert_eq!(first.quote_lots_filled, 68130654);
+        let cached = snapshot.simulate_cached(Side::Ask, 3000);
+        assert_eq!(cached.quote_lots_filled, first.quote_lots_filled);
+
+        // Updating the book bumps the version and invalidates the cached result
+        snapshot.apply_level_update(Side::Bid, 0x58bf, 0);
+        assert_eq!(snapshot.version(), 1);
+        let updated = snapshot.simulate_cached(Side::Ask, 3000);
+        assert_eq!(updated.base_lots_filled, 0x043f * 2);
+        assert_eq!(updated.quote_lots_filled, 0x043f * (0x58b9 + 0x58a7));
+    }
+
+    #[test]
+    fn test_ladder_snapshot_accessors() {
+        let snapshot = LadderSnapshot::new(sol_usdc_ladder(), 100);
+        assert_eq!(snapshot.bids().len(), 3);
+        assert_eq!(snapshot.bids()[0].price_in_ticks, 0x58bf);
+        assert_eq!(snapshot.asks().len(), 3);
+        assert_eq!(snapshot.asks()[0].price_in_ticks, 0x58c0);
+        let ladder: &Ladder = snapshot.as_ref();
+        assert_eq!(ladder.bids.len(), snapshot.bids().len());
+    }
+
+    #[test]
+    fn test_lot_scale() {
+        let ladder = ladder_from_levels(&[(100, 25)], &[(105, 25)]);
+
+        // Caller lots are 10 base lots and 100 quote lots
+        let scaled = LotScale::new(&ladder, 10, 100);
+        // Selling 2 caller lots sells 20 base lots for 2000 quote lots
+        let summary = scaled.simulate_market_sell(Side::Ask, 2);
+        assert_eq!(summary.base_lots_filled, 2);
+        assert_eq!(summary.quote_lots_filled, 20);
+        // Only 25 base lots rest, which is 2.5 caller lots sold for 25 caller quote lots
+        let summary = scaled.simulate_market_sell(Side::Ask, 3);
+        assert_eq!(summary.base_lots_filled, 3);
+        assert_eq!(summary.quote_lots_filled, 25);
+        // 10 caller quote lots buy 9 base lots for 945 quote lots
+        let summary = scaled.simulate_market_sell(Side::Bid, 10);
+        assert_eq!(summary.base_lots_filled, 0);
+        assert_eq!(summary.quote_lots_filled, 10);
+
+        // A scale of 1 matches the ladder itself
+        let summary = LotScale::new(&ladder, 0, 1).simulate_market_sell(Side::Bid, 1000);
+        assert_eq!(summary.base_lots_filled, 9);
+        assert_eq!(summary.quote_lots_filled, 945);
+    }
+
+    #[test]
+    fn test_is_marketable() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // Best bid is 0x58bf and best ask is 0x58c0
+        let limit =
+            |side, price_in_ticks| OrderPacket::new_limit_order_default(side, price_in_ticks, 1);
+        assert!(ladder.is_marketable(&limit(Side::Bid, 0x58c0)));
+        assert!(ladder.is_marketable(&limit(Side::Bid, 0x58d0)));
+        assert!(!ladder.is_marketable(&limit(Side::Bid, 0x58bf)));
+        assert!(ladder.is_marketable(&limit(Side::Ask, 0x58bf)));
+        assert!(!ladder.is_marketable(&limit(Side::Ask, 0x58c0)));
+        assert!(ladder.is_marketable(&OrderPacket::new_post_only_default(Side::Ask, 0x58b0, 1)));
+        assert!(ladder.is_marketable(&OrderPacket::new_ioc_sell_with_slippage(1, 0)));
+
+        let ladder = ladder_from_levels(&[(100, 1)], &[]);
+        assert!(!ladder.is_marketable(&limit(Side::Bid, 1_000)));
+        assert!(ladder.is_marketable(&limit(Side::Ask, 100)));
+    }
+
+    #[test]
+    fn test_is_stop_triggered() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // Best bid is 0x58bf and best ask is 0x58c0
+        assert!(ladder.is_stop_triggered(0x58bf, Side::Ask));
+        assert!(!ladder.is_stop_triggered(0x58be, Side::Ask));
+        assert!(ladder.is_stop_triggered(0x58c0, Side::Bid));
+        assert!(!ladder.is_stop_triggered(0x58c1, Side::Bid));
+
+        let empty = Ladder {
+            bids: vec![],
+            asks: vec![],
+        };
+        assert!(!empty.is_stop_triggered(0, Side::Bid));
+        assert!(!empty.is_stop_triggered(u64::MAX, Side::Ask));
+    }
+
+    #[test]
+    fn test_spread_bps() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // One tick wide at a mid of 22719.5 ticks
+        let spread = ladder.spread_bps().unwrap();
+        assert!((spread - 10_000.0 / 22719.5).abs() < 1e-9);
+
+        assert_eq!(ladder_from_levels(&[], &[(10, 1)]).spread_bps(), None);
+        assert_eq!(ladder_from_levels(&[(0, 1)], &[(0, 1)]).spread_bps(), None);
+    }
+
+    #[test]
+    fn test_book_center_of_mass_in_ticks() {
+        let ladder = ladder_from_levels(&[(100, 10), (90, 30)], &[(110, 20)]);
+        // (100 * 10 + 90 * 30 + 110 * 20) / 60
+        assert_eq!(
+            ladder.book_center_of_mass_in_ticks(),
+            Some(98.33333333333333)
+        );
+        let ladder = ladder_from_levels(&[], &[(110, 20)]);
+        assert_eq!(ladder.book_center_of_mass_in_ticks(), Some(110.0));
+        let ladder = ladder_from_levels(&[], &[]);
+        assert_eq!(ladder.book_center_of_mass_in_ticks(), None);
+    }
+
+    #[test]
+    fn test_band_imbalance() {
+        let ladder = ladder_from_levels(&[(100, 10), (95, 30)], &[(102, 20), (110, 50)]);
+        // Mid is 101, so a band of 1 only covers the touch on each side
+        assert_eq!(
+            ladder.band_imbalance(1),
+            Some((100.0 * 10.0) / (102.0 * 20.0))
+        );
+        assert_eq!(
+            ladder.band_imbalance(6),
+            Some((100.0 * 10.0 + 95.0 * 30.0) / (102.0 * 20.0))
+        );
+        assert_eq!(
+            ladder.band_imbalance(9),
+            Some((100.0 * 10.0 + 95.0 * 30.0) / (102.0 * 20.0 + 110.0 * 50.0))
+        );
+        // Neither touch is within half the spread of the mid
+        assert_eq!(ladder.band_imbalance(0), None);
+        assert_eq!(
+            ladder_from_levels(&[(100, 10)], &[]).band_imbalance(10),
+            None
+        );
+    }
+
+    #[test]
+    fn test_carry_adjusted_price_in_ticks() {
+        let ladder = ladder_from_levels(&[(10_000, 1)], &[(10_100, 1)]);
+        // 5% a year for half a year
+        let price = ladder
+            .carry_adjusted_price_in_ticks(Side::Bid, 500, 182.5)
+            .unwrap();
+        assert!((price - 10_250.0).abs() < 1e-9);
+        let price = ladder
+            .carry_adjusted_price_in_ticks(Side::Ask, 500, 0.0)
+            .unwrap();
+        assert!((price - 10_100.0).abs() < 1e-9);
+        assert_eq!(
+            ladder_from_levels(&[(10_000, 1)], &[]).carry_adjusted_price_in_ticks(
+                Side::Ask,
+                500,
+                1.0
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_well_formed() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // Repeated prices are allowed
+        assert!(ladder.is_well_formed().is_ok());
+        assert!(ladder_from_levels(&[(3, 1), (2, 1)], &[(4, 1), (5, 1)])
+            .is_well_formed()
+            .is_ok());
+        assert!(ladder_from_levels(&[], &[]).is_well_formed().is_ok());
+        // Unsorted bids
+        assert_eq!(
+            ladder_from_levels(&[(2, 1), (3, 1)], &[]).is_well_formed(),
+            Err(LadderError::OutOfOrder {
+                side: Side::Bid,
+                index: 1,
+                price_in_ticks: 3,
+                previous_price_in_ticks: 2,
+            })
+        );
+        // Unsorted asks
+        assert_eq!(
+            ladder_from_levels(&[], &[(5, 1), (4, 1)]).is_well_formed(),
+            Err(LadderError::OutOfOrder {
+                side: Side::Ask,
+                index: 1,
+                price_in_ticks: 4,
+                previous_price_in_ticks: 5,
+            })
+        );
+        // Zero price and zero size
+        assert_eq!(
+            ladder_from_levels(&[(0, 1)], &[]).is_well_formed(),
+            Err(LadderError::ZeroPrice {
+                side: Side::Bid,
+                index: 0,
+            })
+        );
+        let error = ladder_from_levels(&[], &[(4, 1), (4, 0)])
+            .is_well_formed()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            LadderError::ZeroSize {
+                side: Side::Ask,
+                index: 1,
+            }
+        );
+        assert_eq!(error.to_string(), "Ask level 1 has a zero size");
+    }
+
+    #[test]
+    fn test_aggregates_near_overflow() {
+        let ladder = ladder_from_levels(
+            &[(u64::MAX - 1, u64::MAX), (u64::MAX - 2, u64::MAX)],
+            &[(u64::MAX, u64::MAX), (u64::MAX, u64::MAX)],
+        );
+        assert!(ladder.is_well_formed().is_ok());
+
+        for side in [Side::Bid, Side::Ask] {
+            for size_in_lots in [0, 1, u64::MAX / 2, u64::MAX] {
+                ladder.simulate_market_sell(side, size_in_lots);
+                ladder.simulate_with_fill_counts(side, size_in_lots);
+                ladder.simulate_dual_capped(side, size_in_lots, size_in_lots);
+                ladder.simulate_until_ratio(side, size_in_lots, 0.5);
+                ladder.simulate_preserving_queue(side, size_in_lots);
+                ladder.simulated_prints(side, size_in_lots);
+                ladder.worst_price_in_ticks(side, size_in_lots);
+                ladder.fill_histogram(side, size_in_lots, 1 << 32);
+                ladder.spread_crossed_per_lot(side, size_in_lots);
+                ladder.taker_break_even_price_in_ticks(side, size_in_lots, 30);
+                ladder.simulate_with_min_notional(side, size_in_lots, u64::MAX);
+                ladder.price_for_notional(side, size_in_lots);
+                ladder.max_size_within_avg_price(side, size_in_lots);
+                ladder.simulate_pegged(side, 1, size_in_lots);
+                ladder.can_fill_fully(side, size_in_lots, None);
+            }
+            ladder.sweep_entire_side(side);
+            ladder.cost_curve(side);
+            ladder.avg_price_curve(side, u64::MAX / 2);
+            ladder.bucketed(side, 1 << 32);
+            ladder.typed_levels(side);
+            ladder.improve_price_in_ticks(side);
+            ladder.ticks_to_fill(side, 1);
+            ladder.fee_tier_break_even(side, &[(1, 30), (u64::MAX, 0)]);
+            ladder.simulate_checkpoints(side, &[0, 1, u64::MAX / 2, u64::MAX]);
+            ladder.ticks_to_become_best(side, 1);
+        }
+        ladder.sell_base_u128(u64::MAX);
+        ladder.simulate_ioc_buy(u64::MAX, None, 0);
+        ladder.simulate_delta_neutral(u64::MAX);
+        ladder.value_at_price_shock(-10_000);
+        ladder.value_at_price_shock(10_000);
+        ladder.spread_bps();
+        ladder.book_center_of_mass_in_ticks();
+        ladder.band_imbalance(u64::MAX);
+        realized_spread_capture(1, u64::MAX, u64::MAX);
+        ladder.diff(&ladder_from_levels(&[(1, u64::MAX)], &[]));
+        expected_fill(&[(1.0, &ladder), (1.0, &ladder)], Side::Ask, u64::MAX);
+
+        // Totals that don't fit in a u64 saturate instead of wrapping
+        let sold = ladder.sell_base(u64::MAX);
+        assert_eq!(sold.base_lots_filled, u64::MAX);
+        assert_eq!(sold.quote_lots_filled, u64::MAX);
+        let swept = ladder.sweep_entire_side(Side::Bid);
+        assert_eq!(swept.base_lots_filled, u64::MAX);
+        assert_eq!(swept.quote_lots_filled, u64::MAX);
+        assert_eq!(ladder.cost_curve(Side::Ask)[1].1, u128::MAX);
+        assert_eq!(ladder.value_at_price_shock(-10_000).0, u128::MAX);
+        assert_eq!(ladder.max_size_within_avg_price(Side::Ask, 1), u64::MAX);
+    }
+
+    #[test]
+    fn test_value_at_price_shock() {
+        let ladder = ladder_from_levels(
+            &[(100, 10), (99, 10), (95, 10)],
+            &[(101, 10), (102, 10), (110, 10)],
+        );
+        // Mid is 100.5, so neither side survives an unshocked mid
+        assert_eq!(ladder.value_at_price_shock(0), (0, 0));
+        // Down 1% to 99.495
+        assert_eq!(ladder.value_at_price_shock(-100), (1000, 0));
+        // Down 10% to 90.45
+        assert_eq!(ladder.value_at_price_shock(-1000), (1000 + 990 + 950, 0));
+        // Up 2% to 102.51
+        assert_eq!(ladder.value_at_price_shock(200), (0, 1010 + 1020));
+        assert_eq!(
+            ladder_from_levels(&[(100, 10)], &[]).value_at_price_shock(-100),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = ladder_from_levels(&[(100, 10), (99, 10), (98, 5)], &[(101, 10), (102, 10)]);
+        let new = ladder_from_levels(&[(100, 10), (99, 4), (97, 3)], &[(101, 6), (101, 4)]);
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.bids,
+            LadderSideDiff {
+                added: vec![LadderOrder {
+                    price_in_ticks: 97,
+                    size_in_base_lots: 3
+                }],
+                removed: vec![LadderOrder {
+                    price_in_ticks: 98,
+                    size_in_base_lots: 5
+                }],
+                resized: vec![(99, 10, 4)],
+            }
+        );
+        // Repeated levels at 101 add up to the old size, so only 102 changed
+        assert_eq!(
+            diff.asks,
+            LadderSideDiff {
+                added: vec![],
+                removed: vec![LadderOrder {
+                    price_in_ticks: 102,
+                    size_in_base_lots: 10
+                }],
+                resized: vec![],
+            }
+        );
+        assert_eq!(new.diff(&new), LadderDiff::default());
+    }
+
+    #[test]
+    fn test_insertion_index() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // Bids are 0x58bf, 0x58b9, 0x58a7
+        assert_eq!(ladder.insertion_index(Side::Bid, 0x58c0), 0);
+        assert_eq!(ladder.insertion_index(Side::Bid, 0x58b9), 1);
+        assert_eq!(ladder.insertion_index(Side::Bid, 0x58b0), 2);
+        assert_eq!(ladder.insertion_index(Side::Bid, 1), 3);
+        // All asks are at 0x58c0
+        assert_eq!(ladder.insertion_index(Side::Ask, 0x58c0), 0);
+        assert_eq!(ladder.insertion_index(Side::Ask, 0x58c1), 3);
+
+        // FIFO queues a new order behind the levels already at its price
+        assert_eq!(ladder.fifo_insertion_index(Side::Bid, 0x58c0), 0);
+        assert_eq!(ladder.fifo_insertion_index(Side::Bid, 0x58b9), 2);
+        assert_eq!(ladder.fifo_insertion_index(Side::Bid, 0x58b0), 2);
+        assert_eq!(ladder.fifo_insertion_index(Side::Ask, 0x58bf), 0);
+        assert_eq!(ladder.fifo_insertion_index(Side::Ask, 0x58c0), 3);
+    }
+
+    #[test]
+    fn test_ticks_to_fill() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // Best bid is 0x58bf and best ask is 0x58c0
+        assert_eq!(ladder.ticks_to_fill(Side::Bid, 0x58b9), Some(7));
+        assert_eq!(ladder.ticks_to_fill(Side::Bid, 0x58c0), Some(0));
+        assert_eq!(ladder.ticks_to_fill(Side::Bid, 0x58d0), Some(0));
+        assert_eq!(ladder.ticks_to_fill(Side::Ask, 0x58c5), Some(6));
+        assert_eq!(ladder.ticks_to_fill(Side::Ask, 0x58bf), Some(0));
+        assert_eq!(ladder.ticks_to_fill(Side::Ask, 1), Some(0));
+
+        let ladder = ladder_from_levels(&[(100, 1)], &[]);
+        assert_eq!(ladder.ticks_to_fill(Side::Bid, 90), None);
+        assert_eq!(ladder.ticks_to_fill(Side::Ask, 110), Some(10));
+    }
+
+    #[test]
+    fn test_estimated_fill_seconds() {
+        let ladder = ladder_from_levels(&[(100, 1)], &[(110, 1)]);
+        // 10 ticks away at 2 ticks per root second is 25 seconds
+        assert_eq!(
+            ladder.estimated_fill_seconds(Side::Bid, 100, 2.0),
+            Some(25.0)
+        );
+        assert_eq!(
+            ladder.estimated_fill_seconds(Side::Ask, 104, 2.0),
+            Some(4.0)
+        );
+        assert_eq!(
+            ladder.estimated_fill_seconds(Side::Bid, 110, 0.0),
+            Some(0.0)
+        );
+        assert_eq!(ladder.estimated_fill_seconds(Side::Bid, 100, 0.0), None);
+        assert_eq!(
+            ladder.estimated_fill_seconds(Side::Bid, 100, f64::NAN),
+            None
+        );
+
+        let ladder = ladder_from_levels(&[(100, 1)], &[]);
+        assert_eq!(ladder.estimated_fill_seconds(Side::Bid, 100, 2.0), None);
+    }
+
+    #[test]
+    fn test_improve_price_in_ticks() {
+        let ladder = ladder_from_levels(&[(100, 1)], &[(105, 1)]);
+        assert_eq!(ladder.improve_price_in_ticks(Side::Bid), Some(101));
+        assert_eq!(ladder.improve_price_in_ticks(Side::Ask), Some(104));
+
+        // With a one tick spread there is no room to improve, so the best price is joined
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        assert_eq!(ladder.improve_price_in_ticks(Side::Bid), Some(0x58bf));
+        assert_eq!(ladder.improve_price_in_ticks(Side::Ask), Some(0x58c0));
+
+        let ladder = ladder_from_levels(&[(100, 1)], &[]);
+        assert_eq!(ladder.improve_price_in_ticks(Side::Bid), Some(101));
+        assert_eq!(ladder.improve_price_in_ticks(Side::Ask), None);
+        let ladder = ladder_from_levels(&[], &[(1, 1)]);
+        assert_eq!(ladder.improve_price_in_ticks(Side::Ask), Some(1));
+        assert_eq!(ladder.improve_price_in_ticks(Side::Bid), None);
+    }
+
+    #[test]
+    fn test_ticks_to_become_best() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // Best bid is 0x58bf and best ask is 0x58c0
+        assert_eq!(ladder.ticks_to_become_best(Side::Bid, 0x58b9), Some(7));
+        assert_eq!(ladder.ticks_to_become_best(Side::Bid, 0x58bf), Some(0));
+        assert_eq!(ladder.ticks_to_become_best(Side::Bid, 0x58c5), Some(0));
+        assert_eq!(ladder.ticks_to_become_best(Side::Ask, 0x58c2), Some(3));
+        assert_eq!(ladder.ticks_to_become_best(Side::Ask, 0x58c0), Some(0));
+        assert_eq!(ladder.ticks_to_become_best(Side::Ask, 0x58b0), Some(0));
+
+        let ladder = ladder_from_levels(&[(100, 1)], &[]);
+        assert_eq!(ladder.ticks_to_become_best(Side::Ask, 110), None);
+    }
+
+    #[test]
+    fn test_my_fill_at_level() {
+        let ladder = ladder_from_levels(&[(100, 10), (99, 50)], &[(101, 20), (102, 30)]);
+        // 10 lots at 100 and 15 queued ahead at 99 go first
+        assert_eq!(ladder.my_fill_at_level(Side::Bid, 99, 15, 40), 15);
+        assert_eq!(ladder.my_fill_at_level(Side::Bid, 99, 15, 20), 0);
+        // Capped at the 35 lots at 99 behind the queue
+        assert_eq!(ladder.my_fill_at_level(Side::Bid, 99, 15, 1000), 35);
+        assert_eq!(ladder.my_fill_at_level(Side::Ask, 101, 0, 5), 5);
+        assert_eq!(ladder.my_fill_at_level(Side::Ask, 102, 10, 45), 15);
+        assert_eq!(ladder.my_fill_at_level(Side::Ask, 103, 0, 1000), 0);
+
+        // Duplicate levels at the same price form one queue
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        assert_eq!(
+            ladder.my_fill_at_level(Side::Ask, 0x58c0, 0x3036, u64::MAX),
+            0x01e1ff + 0x02a261
+        );
+    }
+
+    #[test]
+    fn test_realized_spread_capture() {
+        assert_eq!(realized_spread_capture(0x58bf, 0x58c0, 100), 100);
+        assert_eq!(realized_spread_capture(0x58c0, 0x58bf, 100), -100);
+        assert_eq!(realized_spread_capture(0x58c0, 0x58c0, 100), 0);
+        assert_eq!(realized_spread_capture(0, u64::MAX, u64::MAX), i64::MAX);
+        assert_eq!(realized_spread_capture(u64::MAX, 0, u64::MAX), i64::MIN);
+    }
+
+    #[test]
+    fn test_typed_levels() {
+        let ladder = ladder_from_levels(&[(100, 10), (99, 5)], &[(101, 7)]);
+        assert_eq!(
+            ladder.typed_levels(Side::Bid),
+            vec![
+                (Ticks::new(100), BaseLots::new(10)),
+                (Ticks::new(99), BaseLots::new(5))
+            ]
+        );
+        assert_eq!(
+            ladder.typed_levels(Side::Ask),
+            vec![(Ticks::new(101), BaseLots::new(7))]
+        );
+    }
+
+    #[test]
+    fn test_bucketed() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // All three asks share a price, so they land in one bucket
+        assert_eq!(
+            ladder.bucketed(Side::Ask, 0),
+            vec![(0x58c0, 0x3036 + 0x01e1ff + 0x02a261)]
+        );
+        // 0x58bf and 0x58b9 share a 16 tick bucket, 0x58a7 falls in the one below
+        assert_eq!(
+            ladder.bucketed(Side::Bid, 16),
+            vec![(0x58b0, 2 * 0x043f), (0x58a0, 0x043f)]
+        );
+        assert_eq!(ladder.bucketed(Side::Bid, 1).len(), 3);
+    }
+
+    #[test]
+    fn test_preview_limit_order() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let metadata = sol_usdc_metadata();
+
+        // Selling 3 SOL at 22.713 takes the top two bids and rests the rest
+        let packet = OrderPacket::new_limit_order_default(Side::Ask, 0x58b9, 3000);
+        let preview = ladder.preview_limit_order(&packet, &metadata);
+        assert_eq!(format!("{:.3}", preview.immediate_fill_base_ui), "2.174");
+        assert_eq!(
+            format!("{:.6}", preview.immediate_fill_quote_ui),
+            "49.384584"
+        );
+        assert_eq!(format!("{:.3}", preview.avg_fill_price), "22.716");
+        assert_eq!(format!("{:.3}", preview.resting_base_ui), "0.826");
+        assert_eq!(
+            preview.resting_price.map(|price| format!("{:.3}", price)),
+            Some("22.713".to_string())
+        );
+
+        // A bid below the asks rests in full
+        let packet = OrderPacket::new_limit_order_default(Side::Bid, 0x58bf, 1000);
+        let preview = ladder.preview_limit_order(&packet, &metadata);
+        assert_eq!(preview.immediate_fill_base_ui, 0.0);
+        assert_eq!(preview.avg_fill_price, 0.0);
+        assert_eq!(preview.resting_base_ui, 1.0);
+        assert_eq!(
+            preview.resting_price.map(|price| format!("{:.3}", price)),
+            Some("22.719".to_string())
+        );
+
+        // A fully filled IOC leaves nothing to rest
+        let packet = OrderPacket::new_ioc_by_lots(
+            Side::Bid,
+            0x58c0,
+            1000,
+            SelfTradeBehavior::Abort,
+            None,
+            0,
+            false,
+        );
+        let preview = ladder.preview_limit_order(&packet, &metadata);
+        assert_eq!(preview.immediate_fill_base_ui, 1.0);
+        assert_eq!(format!("{:.3}", preview.avg_fill_price), "22.720");
+        assert_eq!(preview.resting_base_ui, 0.0);
+        assert_eq!(preview.resting_price, None);
+    }
+
+    #[test]
+    fn test_quote_for_budget_ui() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let metadata = sol_usdc_metadata();
+
+        // Spending 68 USDC only touches the best ask, so there is no impact
+        let preview = ladder.quote_for_budget_ui(Side::Bid, 68.0, &metadata);
+        assert_eq!(format!("{:.3}", preview.base_ui_filled), "2.992");
+        assert_eq!(format!("{:.5}", preview.quote_ui_spent), "67.97824");
+        assert_eq!(format!("{:.3}", preview.avg_price), "22.720");
+        assert_eq!(preview.price_impact_bps, 0.0);
+
+        // Selling 3 SOL walks down the bids
+        let preview = ladder.quote_for_budget_ui(Side::Ask, 3.0, &metadata);
+        assert_eq!(format!("{:.3}", preview.base_ui_filled), "3.000");
+        assert_eq!(format!("{:.3}", preview.avg_price), "22.710");
+        assert_eq!(format!("{:.2}", preview.price_impact_bps), "3.87");
+
+        let preview = ladder.quote_for_budget_ui(Side::Ask, 0.0, &metadata);
+        assert_eq!(preview.base_ui_filled, 0.0);
+        assert_eq!(preview.avg_price, 0.0);
+    }
+
+    #[test]
+    fn test_apply_level_update_insert_in_middle() {
+        let mut ladder = ladder_from_levels(&[(103, 1), (101, 1)], &[(105, 1), (107, 1)]);
+        ladder.apply_level_update(Side::Bid, 102, 5);
+        ladder.apply_level_update(Side::Ask, 106, 5);
+        assert_eq!(
+            ladder,
+            ladder_from_levels(
+                &[(103, 1), (102, 5), (101, 1)],
+                &[(105, 1), (106, 5), (107, 1)]
+            )
+        );
+
+        // Inserting at either end keeps the order too
+        ladder.apply_level_update(Side::Bid, 104, 2);
+        ladder.apply_level_update(Side::Ask, 108, 2);
+        assert_eq!(ladder.bids[0].price_in_ticks, 104);
+        assert_eq!(ladder.asks[3].price_in_ticks, 108);
+    }
+
+    #[test]
+    fn test_apply_level_update_existing() {
+        let mut ladder = ladder_from_levels(&[(103, 1), (101, 1)], &[(105, 1), (107, 1)]);
+        ladder.apply_level_update(Side::Bid, 101, 9);
+        ladder.apply_level_update(Side::Ask, 105, 3);
+        assert_eq!(
+            ladder,
+            ladder_from_levels(&[(103, 1), (101, 9)], &[(105, 3), (107, 1)])
+        );
+
+        // Duplicate levels at one price are merged into a single level
+        let mut ladder = sol_usdc_ladder();
+        ladder.apply_level_update(Side::Ask, 0x58c0, 10);
+        assert_eq!(ladder.asks, ladder_from_levels(&[], &[(0x58c0, 10)]).asks);
+    }
+
+    #[test]
+    fn test_apply_level_update_remove_to_empty() {
+        let mut ladder = ladder_from_levels(&[(103, 1), (101, 1)], &[(105, 1)]);
+        ladder.apply_level_update(Side::Bid, 103, 0);
+        ladder.apply_level_update(Side::Bid, 101, 0);
+        ladder.apply_level_update(Side::Ask, 105, 0);
+        assert!(ladder.bids.is_empty());
+        assert!(ladder.asks.is_empty());
+
+        // Removing a level that does not exist is a no-op
+        ladder.apply_level_update(Side::Ask, 105, 0);
+        assert!(ladder.asks.is_empty());
+    }
 
-        for ask in self.asks.iter() {
-            if remaining_quote_lots == 0 {
-                break;
+    #[test]
+    fn test_with_level_reduced() {
+        let ladder = ladder_from_levels(&[(103, 10), (101, 10)], &[(105, 10)]);
+        let reduced = ladder.with_level_reduced(Side::Bid, 103, 4);
+        assert_eq!(
+            reduced,
+            ladder_from_levels(&[(103, 6), (101, 10)], &[(105, 10)])
+        );
+        // Fewer lots are left for a seller at the top of the book
+        assert_eq!(reduced.sell_base(10).quote_lots_filled, 6 * 103 + 4 * 101);
+        assert_eq!(ladder.sell_base(10).quote_lots_filled, 10 * 103);
+
+        let removed = ladder.with_level_reduced(Side::Ask, 105, 50);
+        assert!(removed.asks.is_empty());
+        assert_eq!(ladder.with_level_reduced(Side::Ask, 106, 5), ladder);
+
+        // Duplicate levels at the price are reduced as one
+        let ladder = sol_usdc_ladder();
+        let reduced = ladder.with_level_reduced(Side::Ask, 0x58c0, 0x3036);
+        assert_eq!(
+            reduced.asks,
+            ladder_from_levels(&[], &[(0x58c0, 0x01e1ff + 0x02a261)]).asks
+        );
+
+        let snapshot = LadderSnapshot::new(ladder, 1);
+        let reduced = snapshot.with_level_reduced(Side::Bid, 0x58bf, 1);
+        assert_eq!(reduced.bids()[0].size_in_base_lots, 0x043e);
+        assert_eq!(reduced.version(), snapshot.version() + 1);
+        assert_eq!(snapshot.bids()[0].size_in_base_lots, 0x043f);
+    }
+
+    #[test]
+    fn test_can_fill_fully() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let total_bids = 3 * 0x043f;
+        assert!(ladder.can_fill_fully(Side::Ask, total_bids, None));
+        assert!(!ladder.can_fill_fully(Side::Ask, total_bids + 1, None));
+        // Only the top two bids are at or above 0x58b9
+        assert!(ladder.can_fill_fully(Side::Ask, 2 * 0x043f, Some(0x58b9)));
+        assert!(!ladder.can_fill_fully(Side::Ask, 2 * 0x043f + 1, Some(0x58b9)));
+
+        // Every ask is at 0x58c0, so a lower limit cannot fill at all. Buys are sized in base
+        // lots too, so the whole ask side fills but one more lot does not
+        let total_asks = 0x3036 + 0x01e1ff + 0x02a261;
+        assert!(ladder.can_fill_fully(Side::Bid, total_asks, Some(0x58c0)));
+        assert!(!ladder.can_fill_fully(Side::Bid, total_asks + 1, None));
+        assert!(!ladder.can_fill_fully(Side::Bid, 1, Some(0x58bf)));
+        assert!(ladder.can_fill_fully(Side::Bid, 0, Some(0)));
+    }
+
+    #[test]
+    fn test_simulate_marketable_limit() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+
+        // Crosses the asks and is fully filled
+        let result = ladder.simulate_marketable_limit(&OrderPacket::new_limit_order_default(
+            Side::Bid,
+            0x58c0,
+            0x3036 + 10,
+        ));
+        assert_eq!(result.taker_summary.base_lots_filled, 0x3036 + 10);
+        assert_eq!(
+            result.taker_summary.quote_lots_filled,
+            (0x3036 + 10) * 0x58c0
+        );
+        assert_eq!(result.resting_base_lots, 0);
+
+        // Sweeps the whole ask side and rests the remainder
+        let total_asks = 0x3036 + 0x01e1ff + 0x02a261;
+        let result = ladder.simulate_marketable_limit(&OrderPacket::new_limit_order_default(
+            Side::Bid,
+            0x58c0,
+            total_asks + 100,
+        ));
+        assert_eq!(result.taker_summary.base_lots_filled, total_asks);
+        assert_eq!(result.resting_base_lots, 100);
+        assert_eq!(result.resting_price_in_ticks, 0x58c0);
+
+        // Only the bids at or above the limit price are taken
+        let result = ladder.simulate_marketable_limit(&OrderPacket::new_limit_order_default(
+            Side::Ask,
+            0x58b9,
+            0x043f * 3,
+        ));
+        assert_eq!(result.taker_summary.base_lots_filled, 0x043f * 2);
+        assert_eq!(
+            result.taker_summary.quote_lots_filled,
+            0x043f * 0x58bf + 0x043f * 0x58b9
+        );
+        assert_eq!(result.taker_quote_lots, 0x043f * 0x58bf + 0x043f * 0x58b9);
+        assert_eq!(result.resting_base_lots, 0x043f);
+        assert_eq!(result.resting_price_in_ticks, 0x58b9);
+
+        // Post-only orders never take
+        let result = ladder.simulate_marketable_limit(&OrderPacket::new_post_only_default(
+            Side::Bid,
+            0x58c0,
+            10,
+        ));
+        assert_eq!(result.taker_summary.base_lots_filled, 0);
+        assert_eq!(result.taker_quote_lots, 0);
+        assert_eq!(result.resting_base_lots, 10);
+    }
+
+    #[test]
+    fn test_typed_lots_filled() {
+        let summary = SimulationSummaryInLots {
+            base_lots_filled: 10,
+            quote_lots_filled: 250,
+        };
+        assert_eq!(summary.base_lots_filled_typed(), BaseLots::new(10));
+        assert_eq!(summary.quote_lots_filled_typed(), QuoteLots::new(250));
+    }
+
+    #[test]
+    fn test_as_position_delta() {
+        let summary = SimulationSummaryInLots {
+            base_lots_filled: 10,
+            quote_lots_filled: 250,
+        };
+        assert_eq!(
+            summary.as_position_delta(Side::Bid),
+            PositionDelta {
+                base_delta: 10,
+                quote_delta: -250
             }
+        );
+        assert_eq!(
+            summary.as_position_delta(Side::Ask),
+            PositionDelta {
+                base_delta: -10,
+                quote_delta: 250
+            }
+        );
+    }
 
-            let max_base_lots_you_can_buy = remaining_quote_lots / ask.price_in_ticks;
-            let amount_lots_to_buy = max_base_lots_you_can_buy.min(ask.size_in_base_lots);
-            base_lots += amount_lots_to_buy;
-            remaining_quote_lots -= amount_lots_to_buy * ask.price_in_ticks;
-        }
+    #[test]
+    fn test_max_size_within_avg_price() {
+        let ladder = ladder_from_levels(&[(100, 10), (90, 10)], &[(100, 10), (110, 10)]);
+        // Buying: 10 lots at 100, then x lots at 110 keeps the average at or below 105 for x <= 10
+        assert_eq!(ladder.max_size_within_avg_price(Side::Bid, 105), 20);
+        // (1000 + 110x) / (10 + x) <= 102 => x <= 2.5
+        assert_eq!(ladder.max_size_within_avg_price(Side::Bid, 102), 12);
+        assert_eq!(ladder.max_size_within_avg_price(Side::Bid, 100), 10);
+        assert_eq!(ladder.max_size_within_avg_price(Side::Bid, 99), 0);
 
-        let quote_lots_used = num_lots_quote - remaining_quote_lots;
-        SimulationSummaryInLots {
-            base_lots_filled: base_lots,
-            quote_lots_filled: quote_lots_used,
-        }
+        // Selling: (1000 + 90x) / (10 + x) >= 98 => x <= 2.5
+        assert_eq!(ladder.max_size_within_avg_price(Side::Ask, 98), 12);
+        assert_eq!(ladder.max_size_within_avg_price(Side::Ask, 90), 20);
+        assert_eq!(ladder.max_size_within_avg_price(Side::Ask, 101), 0);
+
+        // A level past the threshold that fits entirely does not end the walk:
+        // all 21 lots average 2176 / 21 ~= 103.6
+        let ladder = ladder_from_levels(&[], &[(100, 10), (106, 1), (107, 10)]);
+        assert_eq!(ladder.max_size_within_avg_price(Side::Bid, 105), 21);
+        // (1106 + 107x) / (11 + x) <= 104 => x <= 12.7, capped at the level's 10 lots
+        assert_eq!(ladder.max_size_within_avg_price(Side::Bid, 104), 21);
+        // (1106 + 107x) / (11 + x) <= 102 => x <= 3.2
+        assert_eq!(ladder.max_size_within_avg_price(Side::Bid, 102), 14);
+        let ladder = ladder_from_levels(&[(100, 10), (94, 1), (93, 10)], &[]);
+        assert_eq!(ladder.max_size_within_avg_price(Side::Ask, 95), 21);
     }
 
-    fn sell_base(&self, num_lots_base: u64) -> SimulationSummaryInLots {
-        let mut remaining_base_lots = num_lots_base;
-        let mut quote_lots = 0;
+    #[test]
+    fn test_simulate_pegged() {
+        let ladder = ladder_from_levels(&[(100, 10), (99, 10)], &[(101, 10), (102, 10)]);
 
-        for bid in self.bids.iter() {
-            if remaining_base_lots == 0 {
-                break;
+        // Pegged one tick inside the best ask rests without taking
+        let result = ladder.simulate_pegged(Side::Bid, 1, 5);
+        assert_eq!(result.taker_summary.base_lots_filled, 0);
+        assert_eq!(result.resting_base_lots, 5);
+        assert_eq!(result.resting_price_in_ticks, 100);
+
+        // A negative offset crosses into the asks
+        let result = ladder.simulate_pegged(Side::Bid, -1, 15);
+        assert_eq!(result.taker_summary.base_lots_filled, 15);
+        assert_eq!(result.taker_summary.quote_lots_filled, 10 * 101 + 5 * 102);
+        assert_eq!(result.resting_base_lots, 0);
+
+        // Sells peg above the best bid
+        let result = ladder.simulate_pegged(Side::Ask, 1, 5);
+        assert_eq!(result.taker_summary.base_lots_filled, 0);
+        assert_eq!(result.resting_price_in_ticks, 101);
+
+        let empty = ladder_from_levels(&[(100, 10)], &[]);
+        let result = empty.simulate_pegged(Side::Bid, 1, 5);
+        assert_eq!(result.taker_summary.base_lots_filled, 0);
+        assert_eq!(result.resting_base_lots, 0);
+    }
+
+    #[test]
+    fn test_simulate_against_slices() {
+        let asks = [
+            LadderOrder {
+                price_in_ticks: 100,
+                size_in_base_lots: 5,
+            },
+            LadderOrder {
+                price_in_ticks: 110,
+                size_in_base_lots: 5,
+            },
+        ];
+        let bids = [
+            LadderOrder {
+                price_in_ticks: 90,
+                size_in_base_lots: 5,
+            },
+            LadderOrder {
+                price_in_ticks: 80,
+                size_in_base_lots: 5,
+            },
+        ];
+
+        // 500 quote lots take the first ask level, 300 more buy two lots at 110 with 80 left over
+        let summary = simulate_buy_against_asks(&asks, 800);
+        assert_eq!(summary.base_lots_filled, 7);
+        assert_eq!(summary.quote_lots_filled, 720);
+
+        let summary = simulate_sell_against_bids(&bids, 7);
+        assert_eq!(summary.base_lots_filled, 7);
+        assert_eq!(summary.quote_lots_filled, 5 * 90 + 2 * 80);
+
+        assert_eq!(simulate_buy_against_asks(&[], 800).base_lots_filled, 0);
+        assert_eq!(simulate_sell_against_bids(&[], 7).base_lots_filled, 0);
+
+        // The ladder methods delegate to the slice sweeps
+        let ladder = ladder_from_levels(&[(90, 5), (80, 5)], &[(100, 5), (110, 5)]);
+        let from_ladder = ladder.sell_quote(800);
+        let from_slice = simulate_buy_against_asks(&asks, 800);
+        assert_eq!(from_ladder.base_lots_filled, from_slice.base_lots_filled);
+        assert_eq!(from_ladder.quote_lots_filled, from_slice.quote_lots_filled);
+        let from_ladder = ladder.sell_base(7);
+        let from_slice = simulate_sell_against_bids(&bids, 7);
+        assert_eq!(from_ladder.base_lots_filled, from_slice.base_lots_filled);
+        assert_eq!(from_ladder.quote_lots_filled, from_slice.quote_lots_filled);
+    }
+
+    #[test]
+    fn test_simulate_from_levels() {
+        let summary = simulate_from_levels(Side::Bid, &[(100, 5), (110, 5)], 800);
+        assert_eq!(summary.base_lots_filled, 7);
+        assert_eq!(summary.quote_lots_filled, 720);
+
+        let summary = simulate_from_levels(Side::Ask, &[(90, 5), (80, 5)], 7);
+        assert_eq!(summary.base_lots_filled, 7);
+        assert_eq!(summary.quote_lots_filled, 5 * 90 + 2 * 80);
+
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let bids = ladder
+            .bids
+            .iter()
+            .map(|level| (level.price_in_ticks, level.size_in_base_lots))
+            .collect::<Vec<_>>();
+        let summary = simulate_from_levels(Side::Ask, &bids, 3000);
+        assert_eq!(summary.base_lots_filled, 3000);
+        assert_eq!(summary.quote_lots_filled, 68130654);
+    }
+
+    #[test]
+    fn test_simulate_ioc_buy() {
+        let ladder = ladder_from_levels(&[], &[(100, 10), (110, 10)]);
+
+        match ladder.simulate_ioc_buy(2000, None, 0) {
+            IocOutcome::Filled(summary) => {
+                assert_eq!(summary.base_lots_filled, 19);
+                assert_eq!(summary.quote_lots_filled, 1000 + 9 * 110);
             }
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
 
-            let lots_to_fill = remaining_base_lots.min(bid.size_in_base_lots);
-            quote_lots += lots_to_fill * bid.price_in_ticks;
-            remaining_base_lots -= lots_to_fill;
+        // The price cap stops the order at the first level
+        match ladder.simulate_ioc_buy(2000, Some(105), 10) {
+            IocOutcome::Filled(summary) => {
+                assert_eq!(summary.base_lots_filled, 10);
+                assert_eq!(summary.quote_lots_filled, 1000);
+            }
+            outcome => panic!("Unexpected outcome {:?}", outcome),
         }
 
-        let base_lots_used = num_lots_base - remaining_base_lots;
-        SimulationSummaryInLots {
-            base_lots_filled: base_lots_used,
-            quote_lots_filled: quote_lots,
+        // Not enough liquidity under the cap to meet the minimum
+        match ladder.simulate_ioc_buy(2000, Some(105), 11) {
+            IocOutcome::Voided {
+                base_lots_available,
+            } => assert_eq!(base_lots_available, 10),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+
+        // A zero price cap is not a market order and never fills
+        match ladder.simulate_ioc_buy(2000, Some(0), 0) {
+            IocOutcome::Filled(summary) => assert_eq!(summary.base_lots_filled, 0),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        match ladder.simulate_ioc_buy(2000, Some(0), 1) {
+            IocOutcome::Voided {
+                base_lots_available,
+            } => assert_eq!(base_lots_available, 0),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
         }
     }
 
-    fn simulate_market_sell(&self, side: Side, size_in_lots: u64) -> SimulationSummaryInLots {
-        match side {
-            Side::Bid => self.sell_quote(size_in_lots),
-            Side::Ask => self.sell_base(size_in_lots),
+    #[test]
+    fn test_simulate_marketable_limit_ioc_price() {
+        let ladder = ladder_from_levels(&[(100, 10)], &[(110, 10)]);
+        let ioc = |side: Side, price_in_ticks: Option<u64>| {
+            OrderPacket::new_ioc(
+                side,
+                price_in_ticks,
+                5,
+                0,
+                0,
+                0,
+                SelfTradeBehavior::Abort,
+                None,
+                0,
+                false,
+                None,
+                None,
+            )
+        };
+
+        // None is a market order
+        let result = ladder.simulate_marketable_limit(&ioc(Side::Bid, None));
+        assert_eq!(result.taker_summary.base_lots_filled, 5);
+        assert_eq!(result.resting_base_lots, 0);
+
+        // Some(0) is a zero tick limit, which no ask can meet
+        let result = ladder.simulate_marketable_limit(&ioc(Side::Bid, Some(0)));
+        assert_eq!(result.taker_summary.base_lots_filled, 0);
+        assert_eq!(result.resting_base_lots, 0);
+
+        // For a sell, a zero tick limit accepts any bid
+        let result = ladder.simulate_marketable_limit(&ioc(Side::Ask, Some(0)));
+        assert_eq!(result.taker_summary.base_lots_filled, 5);
+        let result = ladder.simulate_marketable_limit(&ioc(Side::Ask, None));
+        assert_eq!(result.taker_summary.base_lots_filled, 5);
+    }
+
+    #[test]
+    fn test_simulate_dual_capped() {
+        let ladder = ladder_from_levels(&[(100, 10), (90, 10)], &[(100, 10), (110, 10)]);
+
+        // The base cap binds inside the second level
+        let result = ladder.simulate_dual_capped(Side::Bid, 15, 10_000);
+        assert_eq!(result.summary.base_lots_filled, 15);
+        assert_eq!(result.summary.quote_lots_filled, 1000 + 5 * 110);
+        assert_eq!(result.binding_cap, Some(BindingCap::Base));
+
+        // The quote cap binds first: 1000 buys the first level, 500 buys 4 more at 110
+        let result = ladder.simulate_dual_capped(Side::Bid, 15, 1500);
+        assert_eq!(result.summary.base_lots_filled, 14);
+        assert_eq!(result.summary.quote_lots_filled, 1000 + 4 * 110);
+        assert_eq!(result.binding_cap, Some(BindingCap::Quote));
+
+        // The base cap is hit exactly at the end of a level
+        let result = ladder.simulate_dual_capped(Side::Ask, 10, 10_000);
+        assert_eq!(result.summary.base_lots_filled, 10);
+        assert_eq!(result.binding_cap, Some(BindingCap::Base));
+
+        // Neither cap binds before the book runs out
+        let result = ladder.simulate_dual_capped(Side::Ask, 100, 10_000);
+        assert_eq!(result.summary.base_lots_filled, 20);
+        assert_eq!(result.summary.quote_lots_filled, 1900);
+        assert_eq!(result.binding_cap, None);
+    }
+
+    #[test]
+    fn test_simulate_delta_neutral() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // 3000 base lots fit in the first ask level at 0x58c0
+        let (summary, hedge_quote_lots) = ladder.simulate_delta_neutral(3000);
+        assert_eq!(summary.base_lots_filled, 3000);
+        assert_eq!(summary.quote_lots_filled, 3000 * 0x58c0);
+        assert_eq!(hedge_quote_lots, summary.quote_lots_filled);
+
+        let ladder = ladder_from_levels(&[], &[(100, 5), (110, 5)]);
+        let (summary, hedge_quote_lots) = ladder.simulate_delta_neutral(7);
+        assert_eq!(summary.base_lots_filled, 7);
+        assert_eq!(hedge_quote_lots, 5 * 100 + 2 * 110);
+
+        // Only the available depth is hedged
+        let (summary, hedge_quote_lots) = ladder.simulate_delta_neutral(20);
+        assert_eq!(summary.base_lots_filled, 10);
+        assert_eq!(hedge_quote_lots, 5 * 100 + 5 * 110);
+    }
+
+    #[test]
+    fn test_price_for_notional() {
+        let ladder = ladder_from_levels(&[(100, 10), (90, 10)], &[(100, 10), (110, 10)]);
+
+        // Spends 1000 on the first ask level and 1100 on the second
+        let price = ladder.price_for_notional(Side::Bid, 2100).unwrap();
+        assert!((price - 2100.0 / 20.0).abs() < 1e-9);
+        // Receives 1000 from the first bid level and rounds up to 2 lots at 90
+        let price = ladder.price_for_notional(Side::Ask, 1100).unwrap();
+        assert!((price - 1180.0 / 12.0).abs() < 1e-9);
+
+        assert_eq!(ladder.price_for_notional(Side::Bid, 2101), None);
+        assert_eq!(ladder.price_for_notional(Side::Ask, 1901), None);
+        assert_eq!(ladder.price_for_notional(Side::Bid, 0), None);
+    }
+
+    #[test]
+    fn test_simulate_until_ratio() {
+        let ladder = ladder_from_levels(&[(100, 10), (90, 10)], &[(100, 10), (110, 10)]);
+        let result = ladder.simulate_until_ratio(Side::Ask, 20, 0.8);
+        assert_eq!(result.base_lots_filled, 16);
+        assert_eq!(result.quote_lots_filled, 1000 + 6 * 90);
+        // The target rounds up to a whole lot
+        let result = ladder.simulate_until_ratio(Side::Ask, 15, 0.5);
+        assert_eq!(result.base_lots_filled, 8);
+        // Ratios outside [0, 1] are clamped
+        assert_eq!(
+            ladder
+                .simulate_until_ratio(Side::Ask, 15, 2.0)
+                .base_lots_filled,
+            15
+        );
+        assert_eq!(
+            ladder
+                .simulate_until_ratio(Side::Ask, 15, -1.0)
+                .base_lots_filled,
+            0
+        );
+        // Buys are sized in quote lots
+        let result = ladder.simulate_until_ratio(Side::Bid, 2000, 0.5);
+        assert_eq!(result.base_lots_filled, 10);
+        assert_eq!(result.quote_lots_filled, 1000);
+    }
+
+    #[test]
+    fn test_taker_break_even_price_in_ticks() {
+        let ladder = ladder_from_levels(&[(100, 10), (90, 10)], &[(100, 10), (110, 10)]);
+        // Buying 20 lots costs 2100 quote lots, plus a 10 bps fee of 2.1 rounded up to 3
+        let price = ladder
+            .taker_break_even_price_in_ticks(Side::Bid, 2100, 10)
+            .unwrap();
+        assert!((price - 2103.0 / 20.0).abs() < 1e-9);
+        // Selling 20 lots raises 1900 quote lots, less a 10 bps fee of 1.9 rounded up to 2
+        let price = ladder
+            .taker_break_even_price_in_ticks(Side::Ask, 20, 10)
+            .unwrap();
+        assert!((price - 1898.0 / 20.0).abs() < 1e-9);
+        assert_eq!(
+            ladder.taker_break_even_price_in_ticks(Side::Bid, 99, 10),
+            None
+        );
+    }
+
+    #[test]
+    fn test_simulate_preserving_queue() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // All three asks are at 0x58c0; the first is taken in full and the second in part
+        assert_eq!(
+            ladder.simulate_preserving_queue(Side::Bid, 0x58c0 * (0x3036 + 100)),
+            vec![(0, 0x3036), (1, 100)]
+        );
+        assert_eq!(
+            ladder.simulate_preserving_queue(Side::Ask, 0x043f + 1),
+            vec![(0, 0x043f), (1, 1)]
+        );
+        assert!(ladder.simulate_preserving_queue(Side::Ask, 0).is_empty());
+    }
+
+    #[test]
+    fn test_spread_crossed_per_lot() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // The mid is 0x58bf.8, half a tick below the best ask
+        assert_eq!(
+            ladder.spread_crossed_per_lot(Side::Ask, 3000),
+            vec![(0x043f, 1), (2 * 0x043f, 7), (3000, 25)]
+        );
+        assert_eq!(
+            ladder.spread_crossed_per_lot(Side::Bid, 0x58c0 * 10),
+            vec![(10, 1)]
+        );
+
+        let ladder = ladder_from_levels(&[(98, 5)], &[(102, 5), (105, 5)]);
+        assert_eq!(
+            ladder.spread_crossed_per_lot(Side::Bid, 102 * 5 + 105 * 2),
+            vec![(5, 2), (7, 5)]
+        );
+        let ladder = ladder_from_levels(&[], &[(102, 5)]);
+        assert!(ladder.spread_crossed_per_lot(Side::Bid, 1000).is_empty());
+    }
+
+    #[test]
+    fn test_fee_tier_break_even() {
+        let ladder = ladder_from_levels(&[(100, 10), (99, 100)], &[(100, 10), (101, 100)]);
+        // One lot at 1% costs 101 ticks all-in, and 10 lots at 0.5% cost 100.5
+        assert_eq!(
+            ladder.fee_tier_break_even(Side::Bid, &[(1, 100), (10, 50)]),
+            Some(10)
+        );
+        // 50 lots average 100.8 ticks, which 0.8% doesn't offset, but 100 lots at 0% do
+        assert_eq!(
+            ladder.fee_tier_break_even(Side::Bid, &[(100, 0), (1, 100), (50, 80)]),
+            Some(100)
+        );
+        // A 0.3% fee on one lot rounds up to a whole quote lot, costing 101 ticks all-in, and
+        // 50 lots cost 5040 plus a fee of 11, or 101.02 ticks
+        assert_eq!(
+            ladder.fee_tier_break_even(Side::Bid, &[(1, 30), (50, 20)]),
+            None
+        );
+        // 100 lots average 100.9 ticks with no fee
+        assert_eq!(
+            ladder.fee_tier_break_even(Side::Bid, &[(1, 30), (50, 20), (100, 0)]),
+            Some(100)
+        );
+        assert_eq!(
+            ladder.fee_tier_break_even(Side::Ask, &[(1, 100), (10, 50)]),
+            Some(10)
+        );
+        // The book cannot fill the cheaper tier's size
+        assert_eq!(
+            ladder.fee_tier_break_even(Side::Ask, &[(1, 100), (1000, 0)]),
+            None
+        );
+        assert_eq!(ladder.fee_tier_break_even(Side::Bid, &[]), None);
+    }
+
+    #[test]
+    fn test_simulate_with_replenishment() {
+        let ladder = ladder_from_levels(&[(100, 10), (90, 10)], &[(110, 10), (120, 10)]);
+        let replenish_levels = HashMap::from([(100, 2), (110, 1)]);
+        // The top bid supplies 30 lots before the sweep reaches 90
+        let summary = ladder.simulate_with_replenishment(Side::Ask, 35, &replenish_levels);
+        assert_eq!(summary.base_lots_filled, 35);
+        assert_eq!(summary.quote_lots_filled, 30 * 100 + 5 * 90);
+        let summary = ladder.simulate_with_replenishment(Side::Bid, 10_000, &replenish_levels);
+        assert_eq!(summary.base_lots_filled, 20 + 10);
+        assert_eq!(summary.quote_lots_filled, 20 * 110 + 10 * 120);
+        // Without refills this is the plain sweep
+        let summary = ladder.simulate_with_replenishment(Side::Ask, 35, &HashMap::new());
+        assert_eq!(summary.base_lots_filled, 20);
+        assert_eq!(
+            summary.quote_lots_filled,
+            ladder.sell_base(35).quote_lots_filled
+        );
+    }
+
+    #[test]
+    fn test_simulate_with_my_order() {
+        let ladder = ladder_from_levels(&[(100, 10)], &[(101, 10), (102, 10)]);
+        let maker_id = Pubkey::new_unique();
+        let my_ask = (
+            FIFOOrderId::new_from_untyped(101, 5),
+            PhoenixOrder {
+                num_base_lots: 4,
+                maker_id,
+            },
+        );
+        // The existing 10 lots at 101 fill first, then 3 of my 4
+        let (summary, mine) = ladder.simulate_with_my_order(my_ask, Side::Bid, 13 * 101);
+        assert_eq!(summary.base_lots_filled, 13);
+        assert_eq!(summary.quote_lots_filled, 13 * 101);
+        assert_eq!(mine, 3);
+        let (summary, mine) = ladder.simulate_with_my_order(my_ask, Side::Bid, 10_000);
+        assert_eq!(summary.base_lots_filled, 24);
+        assert_eq!(mine, 4);
+        // Sellers never reach an ask
+        let (_, mine) = ladder.simulate_with_my_order(my_ask, Side::Ask, 10);
+        assert_eq!(mine, 0);
+
+        // My bid queues behind the 10 lots already bid at 100
+        let my_bid = (
+            FIFOOrderId::new_from_untyped(100, !5),
+            PhoenixOrder {
+                num_base_lots: 4,
+                maker_id,
+            },
+        );
+        let (_, mine) = ladder.simulate_with_my_order(my_bid, Side::Ask, 12);
+        assert_eq!(mine, 2);
+        // The original book is left untouched
+        assert_eq!(ladder.bids.len(), 1);
+    }
+
+    #[test]
+    fn test_simulate_checkpoints() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let sizes = [5_000_000_000, 0, 100, 3000, 1087, 1088, 100, u64::MAX];
+        for side in [Side::Bid, Side::Ask] {
+            let summaries = ladder.simulate_checkpoints(side, &sizes);
+            assert_eq!(summaries.len(), sizes.len());
+            for (&size, summary) in sizes.iter().zip(summaries.iter()) {
+                let expected = ladder.simulate_market_sell(side, size);
+                assert_eq!(summary.base_lots_filled, expected.base_lots_filled);
+                assert_eq!(summary.quote_lots_filled, expected.quote_lots_filled);
+            }
         }
+        assert!(ladder.simulate_checkpoints(Side::Bid, &[]).is_empty());
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use phoenix::state::markets::LadderOrder;
+    #[test]
+    fn test_simulate_with_fees() {
+        let ladder = ladder_from_levels(&[(100, 10)], &[(100, 10)]);
+        // Mirror trades of 10 base lots for 1000 quote lots at 30 bps
+        let buy = ladder.simulate_buy_with_fees(1000, 30);
+        let sell = ladder.simulate_sell_with_fees(10, 30);
+        assert_eq!(buy.summary.base_lots_filled, 10);
+        assert_eq!(sell.summary.base_lots_filled, 10);
+        assert_eq!(buy.gross_quote_lots, 1000);
+        assert_eq!(sell.gross_quote_lots, 1000);
+        assert_eq!(buy.fee_quote_lots, 3);
+        assert_eq!(sell.fee_quote_lots, 3);
+        // The buyer pays the fee on top, and the seller has it taken out
+        assert_eq!(buy.net_quote_lots, 1003);
+        assert_eq!(sell.net_quote_lots, 997);
 
-    struct Fixture {
-        pub ladder: Ladder,
-        pub atoms_in_base_lot: f64,
-        pub atoms_in_quote_lot: f64,
-        pub atoms_in_base_unit: f64,
-        pub atoms_in_quote_unit: f64,
+        // Fees round up to a whole quote lot
+        let sell = ladder.simulate_sell_with_fees(1, 1);
+        assert_eq!(sell.fee_quote_lots, 1);
+        assert_eq!(sell.net_quote_lots, 99);
+        assert_eq!(ladder.simulate_sell_with_fees(0, 30).fee_quote_lots, 0);
     }
 
-    // This is a very simplified ladder for SOL/USDC on Phoenix
-    fn get_sol_usdc_ladder() -> Fixture {
-        let ladder = Ladder {
-            bids: vec![
-                LadderOrder {
-                    price_in_ticks: 0x58bf,
-                    size_in_base_lots: 0x043f,
-                },
-                LadderOrder {
-                    price_in_ticks: 0x58b9,
-                    size_in_base_lots: 0x043f,
-                },
-                LadderOrder {
-                    price_in_ticks: 0x58a7,
-                    size_in_base_lots: 0x043f,
-                },
-            ],
-            asks: vec![
-                LadderOrder {
-                    price_in_ticks: 0x58c0,
-                    size_in_base_lots: 0x3036,
-                },
-                LadderOrder {
-                    price_in_ticks: 0x58c0,
-                    size_in_base_lots: 0x01e1ff,
-                },
-                LadderOrder {
-                    price_in_ticks: 0x58c0,
-                    size_in_base_lots: 0x02a261,
-                },
-            ],
-        };
-        let fixture = Fixture {
-            ladder,
-            atoms_in_base_lot: 1e6,
-            atoms_in_quote_lot: 1.,
-            atoms_in_base_unit: 1e9,
-            atoms_in_quote_unit: 1e6,
-        };
-        fixture
+    #[test]
+    fn test_simulate_with_min_notional() {
+        let ladder = ladder_from_levels(&[(100, 10)], &[(110, 10)]);
+        // Selling 5 lots into the 100 bid is a notional of 500 quote lots
+        match ladder.simulate_with_min_notional(Side::Ask, 5, 500) {
+            IocOutcome::Filled(summary) => {
+                assert_eq!(summary.base_lots_filled, 5);
+                assert_eq!(summary.quote_lots_filled, 500);
+            }
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        match ladder.simulate_with_min_notional(Side::Ask, 5, 501) {
+            IocOutcome::Voided {
+                base_lots_available,
+            } => assert_eq!(base_lots_available, 5),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+
+        // A 1000 quote lot buy only spends 990 on whole lots at 110
+        match ladder.simulate_with_min_notional(Side::Bid, 1000, 990) {
+            IocOutcome::Filled(summary) => assert_eq!(summary.base_lots_filled, 9),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        match ladder.simulate_with_min_notional(Side::Bid, 1000, 991) {
+            IocOutcome::Voided {
+                base_lots_available,
+            } => assert_eq!(base_lots_available, 9),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
     }
 
-    fn lots_to_unit_amount(lots: u64, lots_to_atoms: f64, atoms_to_unit: f64) -> f64 {
-        let atoms = lots_to_atoms * lots as f64;
-        let unit = atoms / atoms_to_unit;
-        unit
+    #[test]
+    fn test_impact_adjusted_price_in_ticks() {
+        let ladder = ladder_from_levels(&[(100, 10)], &[(110, 10)]);
+        // Within the visible book this is the sweep average
+        assert_eq!(
+            ladder.impact_adjusted_price_in_ticks(Side::Ask, 5, 2.0),
+            Some(100.0)
+        );
+        assert_eq!(
+            ladder.impact_adjusted_price_in_ticks(Side::Bid, 500, 2.0),
+            Some(110.0)
+        );
+        // 9 residual lots fill 2 * sqrt(9) ticks below the last bid
+        assert_eq!(
+            ladder.impact_adjusted_price_in_ticks(Side::Ask, 19, 2.0),
+            Some((1000.0 + 9.0 * 94.0) / 19.0)
+        );
+        // 490 residual quote lots are 4 base lots at 110, filled 2 * sqrt(4) ticks higher
+        assert_eq!(
+            ladder.impact_adjusted_price_in_ticks(Side::Bid, 1590, 2.0),
+            Some((1100.0 + 4.0 * 114.0) / 14.0)
+        );
+        assert_eq!(
+            ladder.impact_adjusted_price_in_ticks(Side::Ask, 19, 0.0),
+            Some(100.0)
+        );
+        assert_eq!(
+            ladder.impact_adjusted_price_in_ticks(Side::Ask, 19, -1.0),
+            None
+        );
+        assert_eq!(
+            ladder_from_levels(&[], &[]).impact_adjusted_price_in_ticks(Side::Ask, 19, 2.0),
+            None
+        );
     }
 
     #[test]
-    fn test_empty_ladder_sell() {
-        let ladder = Ladder {
-            bids: vec![],
-            asks: vec![],
-        };
-        let result = ladder.simulate_market_sell(Side::Ask, 1000);
-        assert_eq!(result.base_lots_filled, 0);
-        assert_eq!(result.quote_lots_filled, 0);
+    fn test_recommended_packet() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        // Selling 1500 lots reaches the 0x58b9 bid, and 10 bps of it is 22.7 ticks
+        let packet = ladder.recommended_packet(Side::Ask, 1500, 10, 7).unwrap();
+        assert_eq!(
+            packet,
+            OrderPacket::new_ioc_by_lots(
+                Side::Ask,
+                0x58b9 - 22,
+                1500,
+                SelfTradeBehavior::CancelProvide,
+                None,
+                7,
+                false,
+            )
+        );
+        // The packet fills the simulated size against the book
+        let result = ladder.simulate_marketable_limit(&packet);
+        assert_eq!(result.taker_summary.base_lots_filled, 1500);
+
+        // A buy is sized to the base lots the quote budget fills
+        let packet = ladder
+            .recommended_packet(Side::Bid, 0x58c0 * 10 + 5, 0, 7)
+            .unwrap();
+        assert_eq!(packet.num_base_lots().as_u64(), 10);
+        assert_eq!(packet.get_price_in_ticks().as_u64(), 0x58c0);
+
+        assert!(ladder.recommended_packet(Side::Bid, 0, 10, 7).is_none());
+        let ladder = ladder_from_levels(&[(100, 1)], &[]);
+        assert!(ladder.recommended_packet(Side::Bid, 1_000, 10, 7).is_none());
     }
 
     #[test]
-    fn test_sell_more_than_available() {
+    fn test_worst_price_in_ticks() {
         let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        assert_eq!(ladder.worst_price_in_ticks(Side::Ask, 3000), Some(0x58a7));
+        assert_eq!(ladder.worst_price_in_ticks(Side::Ask, 0x043f), Some(0x58bf));
+        assert_eq!(
+            ladder.worst_price_in_ticks(Side::Bid, 0x58c0 * 10),
+            Some(0x58c0)
+        );
+        assert_eq!(ladder.worst_price_in_ticks(Side::Ask, 0), None);
+        // Too small to buy a single lot
+        assert_eq!(ladder.worst_price_in_ticks(Side::Bid, 0x58bf), None);
+    }
 
-        // Compute the max lots you can sell
-        let max_lots_purchaseable: u64 = ladder.bids.iter().map(|bid| bid.size_in_base_lots).sum();
+    #[test]
+    fn test_simulated_prints() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        assert_eq!(
+            ladder.simulated_prints(Side::Ask, 3000),
+            vec![
+                (0x58bf, 0x043f),
+                (0x58b9, 0x043f),
+                (0x58a7, 3000 - 2 * 0x043f)
+            ]
+        );
+        // Repeated levels at one price produce one print each
+        assert_eq!(
+            ladder.simulated_prints(Side::Bid, 0x58c0 * (0x3036 + 1)),
+            vec![(0x58c0, 0x3036), (0x58c0, 1)]
+        );
+        assert!(ladder.simulated_prints(Side::Ask, 0).is_empty());
+    }
 
-        // Sell twice as much, and assert that only the max is filled
-        let to_purchase = max_lots_purchaseable * 2;
-        let result = ladder.simulate_market_sell(Side::Ask, to_purchase);
-        assert_eq!(result.base_lots_filled, max_lots_purchaseable);
-        assert!(result.quote_lots_filled > 0);
+    #[test]
+    fn test_fill_histogram() {
+        let ladder = ladder_from_levels(
+            &[(109, 5), (105, 5), (99, 5)],
+            &[(101, 5), (104, 5), (112, 5)],
+        );
+        // Selling 12 base lots fills 5 @ 109 and 5 @ 105 in the 100 bucket, then 2 @ 99
+        assert_eq!(
+            ladder.fill_histogram(Side::Ask, 12, 10),
+            vec![(100, 10), (90, 2)]
+        );
+        // Buying with 1000 quote lots fills 5 @ 101 and 4 @ 104
+        assert_eq!(ladder.fill_histogram(Side::Bid, 1000, 10), vec![(100, 9)]);
+        assert_eq!(
+            ladder.fill_histogram(Side::Bid, 1000, 0),
+            vec![(101, 5), (104, 4)]
+        );
+        assert!(ladder.fill_histogram(Side::Ask, 0, 10).is_empty());
     }
 
     #[test]
-    fn test_buy_more_than_available() {
+    fn test_sell_base_u128() {
         let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let narrow = ladder.sell_base(3000);
+        let wide = ladder.sell_base_u128(3000);
+        assert_eq!(wide.base_lots_filled, narrow.base_lots_filled);
+        assert_eq!(wide.quote_lots_filled, narrow.quote_lots_filled as u128);
 
-        // Compute the max lots you can buy (from available asks)
-        let max_lots_sellable: u64 = ladder
-            .asks
-            .iter()
-            .map(|ask| ask.size_in_base_lots * ask.price_in_ticks)
-            .sum();
+        // The notional here does not fit in a u64
+        let deep = ladder_from_levels(&[(u64::MAX, 2), (u64::MAX - 1, 2)], &[]);
+        let wide = deep.sell_base_u128(3);
+        assert_eq!(wide.base_lots_filled, 3);
+        assert_eq!(
+            wide.quote_lots_filled,
+            2 * u64::MAX as u128 + (u64::MAX - 1) as u128
+        );
+    }
 
-        // Try to buy twice as much, which means you are selling twice as much base
-        let to_sell = max_lots_sellable * 2;
-        let result = ladder.simulate_market_sell(Side::Bid, to_sell);
+    #[test]
+    fn test_sweep_entire_side() {
+        let Fixture { ladder, .. } = get_sol_usdc_ladder();
+        let total_asks = 0x3036 + 0x01e1ff + 0x02a261;
+        let result = ladder.sweep_entire_side(Side::Bid);
+        assert_eq!(result.base_lots_filled, total_asks);
+        assert_eq!(result.quote_lots_filled, total_asks * 0x58c0);
 
-        assert_eq!(result.quote_lots_filled, max_lots_sellable);
-        assert!(result.quote_lots_filled > 0);
+        let result = ladder.sweep_entire_side(Side::Ask);
+        assert_eq!(result.base_lots_filled, 0x043f * 3);
+        assert_eq!(
+            result.quote_lots_filled,
+            0x043f * (0x58bf + 0x58b9 + 0x58a7)
+        );
+
+        // Totals past u64::MAX saturate instead of overflowing
+        let deep = ladder_from_levels(&[(u64::MAX, u64::MAX), (1, 1)], &[]);
+        let result = deep.sweep_entire_side(Side::Ask);
+        assert_eq!(result.base_lots_filled, u64::MAX);
+        assert_eq!(result.quote_lots_filled, u64::MAX);
+    }
+
+    fn orderbook_with_asks(asks: &[(FIFOOrderId, u64)]) -> Orderbook<FIFOOrderId, PhoenixOrder> {
+        let mut orderbook = Orderbook {
+            raw_base_units_per_base_lot: 1.0,
+            quote_units_per_raw_base_unit_per_tick: 1.0,
+            bids: Default::default(),
+            asks: Default::default(),
+        };
+        for &(order_id, num_base_lots) in asks {
+            orderbook.asks.insert(
+                order_id,
+                PhoenixOrder {
+                    num_base_lots,
+                    maker_id: Default::default(),
+                },
+            );
+        }
+        orderbook
+    }
+
+    #[test]
+    fn test_expiring_base_lots() {
+        let unfiltered = ladder_from_levels(&[(100, 10), (99, 10)], &[(101, 10), (102, 10)]);
+        let filtered = ladder_from_levels(&[(100, 10), (99, 4)], &[(102, 10)]);
+        assert_eq!(expiring_base_lots(&unfiltered, &filtered), (6, 10));
+        assert_eq!(expiring_base_lots(&unfiltered, &unfiltered), (0, 0));
+    }
+
+    #[test]
+    fn test_expected_fill() {
+        let full = ladder_from_levels(&[(100, 10)], &[]);
+        let thin = ladder_from_levels(&[(100, 2)], &[]);
+
+        let summary = expected_fill(&[(0.75, &full), (0.25, &thin)], Side::Ask, 10);
+        assert_eq!(summary.base_lots_filled, 8);
+        assert_eq!(summary.quote_lots_filled, 800);
+
+        // Weights are normalized, and non-positive weights are ignored
+        let summary = expected_fill(&[(3.0, &full), (1.0, &thin), (-1.0, &thin)], Side::Ask, 10);
+        assert_eq!(summary.base_lots_filled, 8);
+
+        let summary = expected_fill(&[(0.0, &full), (f64::NAN, &thin)], Side::Ask, 10);
+        assert_eq!(summary.base_lots_filled, 0);
+        assert_eq!(expected_fill(&[], Side::Bid, 1000).quote_lots_filled, 0);
+    }
+
+    #[test]
+    fn test_simulate_by_trader() {
+        let maker_a = Pubkey::new_unique();
+        let maker_b = Pubkey::new_unique();
+        let first = FIFOOrderId::new_from_untyped(100, 1);
+        let second = FIFOOrderId::new_from_untyped(100, 2);
+        let third = FIFOOrderId::new_from_untyped(101, 3);
+        let mut orderbook = orderbook_with_asks(&[(first, 10), (second, 10), (third, 10)]);
+        orderbook.asks.get_mut(&first).unwrap().maker_id = maker_a;
+        orderbook.asks.get_mut(&second).unwrap().maker_id = maker_b;
+        orderbook.asks.get_mut(&third).unwrap().maker_id = maker_a;
+
+        // 10 @ 100 from A, 10 @ 100 from B, then 5 @ 101 from A
+        let fills = simulate_by_trader(&orderbook, Side::Bid, 2505);
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[&maker_a], 15);
+        assert_eq!(fills[&maker_b], 10);
+
+        assert!(simulate_by_trader(&orderbook, Side::Bid, 99).is_empty());
+        assert!(simulate_by_trader(&orderbook, Side::Ask, 10).is_empty());
+    }
+
+    #[test]
+    fn test_simulate_with_self_trades() {
+        let mine = FIFOOrderId::new_from_untyped(100, 2);
+        let theirs_before = FIFOOrderId::new_from_untyped(100, 1);
+        let theirs_after = FIFOOrderId::new_from_untyped(101, 3);
+        let orderbook = orderbook_with_asks(&[(theirs_before, 10), (mine, 10), (theirs_after, 10)]);
+        let my_order_ids = BTreeSet::from([mine]);
+        let is_mine = |order_id: &FIFOOrderId| my_order_ids.contains(order_id);
+
+        // Without any of my orders in the set, this is a plain sweep: 10 @ 100 + 5 @ 100
+        let result = simulate_with_self_trades(
+            &orderbook,
+            Side::Bid,
+            1500,
+            |_| false,
+            SelfTradeBehavior::Abort,
+        );
+        assert_eq!(result.base_lots_filled, 15);
+        assert_eq!(result.quote_lots_filled, 1500);
+
+        let result = simulate_with_self_trades(
+            &orderbook,
+            Side::Bid,
+            1500,
+            is_mine,
+            SelfTradeBehavior::Abort,
+        );
+        assert_eq!(result.base_lots_filled, 0);
+        assert_eq!(result.quote_lots_filled, 0);
+
+        // My order is canceled and the remaining 500 quote lots buy 4 lots at 101
+        let result = simulate_with_self_trades(
+            &orderbook,
+            Side::Bid,
+            1500,
+            is_mine,
+            SelfTradeBehavior::CancelProvide,
+        );
+        assert_eq!(result.base_lots_filled, 14);
+        assert_eq!(result.quote_lots_filled, 1000 + 4 * 101);
+
+        // The overlap with my order is removed from the taker order
+        let result = simulate_with_self_trades(
+            &orderbook,
+            Side::Bid,
+            1500,
+            is_mine,
+            SelfTradeBehavior::DecrementTake,
+        );
+        assert_eq!(result.base_lots_filled, 10);
+        assert_eq!(result.quote_lots_filled, 1000);
+
+        // 50 quote lots left after the first order cannot buy a lot of mine, so nothing aborts
+        let result = simulate_with_self_trades(
+            &orderbook,
+            Side::Bid,
+            1050,
+            is_mine,
+            SelfTradeBehavior::Abort,
+        );
+        assert_eq!(result.base_lots_filled, 10);
+        assert_eq!(result.quote_lots_filled, 1000);
     }
 
     #[test]
@@ -217,4 +4216,51 @@ mod test {
             );
         }
     }
+
+    fn arb_ladder() -> impl proptest::strategy::Strategy<Value = Ladder> {
+        use proptest::prelude::*;
+        // Bounded so that the total notional of a side fits comfortably in a u64
+        let levels = || prop::collection::btree_map(1_u64..1_000_000, 1_u64..1_000_000, 0..20);
+        (levels(), levels()).prop_map(|(bids, asks)| Ladder {
+            bids: bids
+                .into_iter()
+                .rev()
+                .map(|(price_in_ticks, size_in_base_lots)| LadderOrder {
+                    price_in_ticks,
+                    size_in_base_lots,
+                })
+                .collect(),
+            asks: asks
+                .into_iter()
+                .map(|(price_in_ticks, size_in_base_lots)| LadderOrder {
+                    price_in_ticks,
+                    size_in_base_lots,
+                })
+                .collect(),
+        })
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_simulate_market_sell_invariants(
+            ladder in arb_ladder(),
+            size_in_lots in 0_u64..1_000_000_000_000,
+            extra_lots in 0_u64..1_000_000_000_000,
+        ) {
+            for side in [Side::Bid, Side::Ask] {
+                let result = ladder.simulate_market_sell(side, size_in_lots);
+                match side {
+                    Side::Bid => proptest::prop_assert!(result.quote_lots_filled <= size_in_lots),
+                    Side::Ask => proptest::prop_assert!(result.base_lots_filled <= size_in_lots),
+                }
+
+                let larger = ladder.simulate_market_sell(side, size_in_lots + extra_lots);
+                proptest::prop_assert!(larger.quote_lots_filled >= result.quote_lots_filled);
+
+                let empty = ladder.simulate_market_sell(side, 0);
+                proptest::prop_assert_eq!(empty.base_lots_filled, 0);
+                proptest::prop_assert_eq!(empty.quote_lots_filled, 0);
+            }
+        }
+    }
 }
@@ -2,4 +2,6 @@ pub use phoenix_sdk_core::orderbook;
 pub mod ladder_utils;
 pub mod order_packet_template;
 pub mod sdk_client;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_support;
 pub mod utils;
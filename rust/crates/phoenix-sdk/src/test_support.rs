@@ -0,0 +1,32 @@
+//! Fixtures for building `Ladder`s in tests. Enabled with the `test-util` feature.
+use phoenix::state::markets::{Ladder, LadderOrder};
+
+/// Market metadata matching `sol_usdc_ladder`.
+pub use phoenix_sdk_core::test_support::sol_usdc_metadata;
+
+/// Builds a `Ladder` from `(price_in_ticks, size_in_base_lots)` levels.
+/// Bids are expected in descending price order and asks in ascending price order.
+pub fn ladder_from_levels(bids: &[(u64, u64)], asks: &[(u64, u64)]) -> Ladder {
+    let to_orders = |levels: &[(u64, u64)]| {
+        levels
+            .iter()
+            .map(|&(price_in_ticks, size_in_base_lots)| LadderOrder {
+                price_in_ticks,
+                size_in_base_lots,
+            })
+            .collect::<Vec<_>>()
+    };
+    Ladder {
+        bids: to_orders(bids),
+        asks: to_orders(asks),
+    }
+}
+
+/// A very simplified ladder for SOL/USDC on Phoenix.
+/// Base lots are 1e6 atoms (0.001 SOL) and quote lots are 1 atom (0.000001 USDC).
+pub fn sol_usdc_ladder() -> Ladder {
+    ladder_from_levels(
+        &[(0x58bf, 0x043f), (0x58b9, 0x043f), (0x58a7, 0x043f)],
+        &[(0x58c0, 0x3036), (0x58c0, 0x01e1ff), (0x58c0, 0x02a261)],
+    )
+}